@@ -2,16 +2,111 @@ use std::{
     fmt::Display,
     fs::File,
     io::{Read, Write},
+    path::Path,
+};
+
+use crate::{
+    BmpDecoderOptions, BmpEncoderOptions, Compression, HeaderVersion, Limits, Luma, Pixel, Rgb,
+    Rgba,
 };
 
 #[derive(Debug)]
 pub enum Error {
     FileError(std::io::Error),
-    InvalidSignature,
-    InvalidHeaderSize(u32),
-    UnsupportedPlaneCount(u16),
-    UnsupportedColorDepth(u16),
-    UnsupportedCompression(u32),
+    // The file ended needed bytes short of what was being read at offset
+    Truncated {
+        offset: usize,
+        needed: usize,
+    },
+    // Header errors carry the byte offset and name of the offending field
+    InvalidSignature {
+        offset: usize,
+        field: &'static str,
+        value: [u8; 2],
+    },
+    InvalidHeaderSize {
+        offset: usize,
+        field: &'static str,
+        value: u32,
+    },
+    UnsupportedPlaneCount {
+        offset: usize,
+        field: &'static str,
+        value: u16,
+    },
+    UnsupportedColorDepth {
+        offset: usize,
+        field: &'static str,
+        value: u16,
+    },
+    UnsupportedCompression {
+        offset: usize,
+        field: &'static str,
+        value: u32,
+    },
+    InvalidPaletteSize {
+        offset: usize,
+        field: &'static str,
+        value: u32,
+    },
+    InvalidDataOffset {
+        offset: usize,
+        field: &'static str,
+        value: u32,
+    },
+    InvalidPaletteIndex(u8),
+    MalformedRle {
+        x: u32,
+        y: u32,
+    },
+    InvalidEncoderOptions(&'static str),
+    TooManyColors(usize),
+    InvalidRowLength {
+        expected: u32,
+        got: usize,
+    },
+    RowCountMismatch {
+        expected: u32,
+        got: u32,
+    },
+    RegionOutOfBounds {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    // Names the limit the headers asked to exceed
+    LimitExceeded(&'static str),
+    // The pixels don't make whole rows of the given width
+    InvalidDimensions {
+        width: u32,
+        len: usize,
+    },
+    // Bitmaps store signed 32 bit dimensions and 32 bit sizes
+    ImageTooLarge {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl Error {
+    // The parsers only see the rest of the buffer, so they record offsets
+    // as the number of bytes left. Whoever holds the whole buffer turns them
+    // into offsets from its start.
+    pub(crate) fn locate(mut self, buff_len: usize) -> Self {
+        match &mut self {
+            Error::Truncated { offset, .. }
+            | Error::InvalidSignature { offset, .. }
+            | Error::InvalidHeaderSize { offset, .. }
+            | Error::UnsupportedPlaneCount { offset, .. }
+            | Error::UnsupportedColorDepth { offset, .. }
+            | Error::UnsupportedCompression { offset, .. }
+            | Error::InvalidPaletteSize { offset, .. } => *offset = buff_len - *offset,
+            _ => {}
+        }
+
+        self
+    }
 }
 
 impl From<std::io::Error> for Error {
@@ -20,206 +115,1430 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::FileError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::FileError(e) => write!(f, "File Error: {e}"),
-            Error::InvalidSignature => write!(f, "Invalid Signature"),
-            Error::InvalidHeaderSize(e) => write!(f, "Invalid header size, expected 40, got {e}"),
-            Error::UnsupportedPlaneCount(e) => {
-                write!(f, "Unsupported plane count, expected 1, got {e}")
+            Error::Truncated { offset, needed } => {
+                write!(f, "File is truncated, needed {needed} bytes at offset {offset}")
+            }
+            Error::InvalidSignature {
+                offset,
+                field,
+                value,
+            } => write!(
+                f,
+                "Invalid signature {:?} in {field} at offset {offset}",
+                String::from_utf8_lossy(value)
+            ),
+            Error::InvalidHeaderSize {
+                offset,
+                field,
+                value,
+            } => write!(
+                f,
+                "Invalid header size {value} in {field} at offset {offset}, \
+                 expected 12, 16, 40, 52, 56, 64, 108 or 124"
+            ),
+            Error::UnsupportedPlaneCount {
+                offset,
+                field,
+                value,
+            } => write!(
+                f,
+                "Unsupported plane count {value} in {field} at offset {offset}, expected 1"
+            ),
+            Error::UnsupportedColorDepth {
+                offset,
+                field,
+                value,
+            } => write!(
+                f,
+                "Unsupported color depth of {value} bpp in {field} at offset {offset}"
+            ),
+            Error::UnsupportedCompression {
+                offset,
+                field,
+                value,
+            } => write!(
+                f,
+                "Unsupported compression {value} in {field} at offset {offset} \
+                 for the image's color depth"
+            ),
+            Error::InvalidPaletteSize {
+                offset,
+                field,
+                value,
+            } => write!(
+                f,
+                "Palette size {value} in {field} at offset {offset} is too large for the color depth"
+            ),
+            Error::InvalidPaletteIndex(e) => write!(f, "Palette index {e} is out of range"),
+            Error::MalformedRle { x, y } => {
+                write!(f, "Malformed RLE data at pixel ({x}, {y})")
+            }
+            Error::InvalidDataOffset {
+                offset,
+                field,
+                value,
+            } => write!(
+                f,
+                "Pixel data offset {value} in {field} at offset {offset} is past the end of the file"
+            ),
+            Error::InvalidEncoderOptions(e) => write!(f, "Invalid encoder options: {e}"),
+            Error::TooManyColors(e) => write!(f, "Image has more than {e} colors"),
+            Error::InvalidRowLength { expected, got } => {
+                write!(f, "Invalid row length, expected {expected}, got {got}")
             }
-            Error::UnsupportedColorDepth(e) => {
-                write!(f, "Unsupported color depth, expected 24, got {e}")
+            Error::RowCountMismatch { expected, got } => {
+                write!(f, "Invalid row count, expected {expected}, got {got}")
             }
-            Error::UnsupportedCompression(e) => {
-                write!(f, "Unsupported compression, expected 0, got {e}")
+            Error::RegionOutOfBounds {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                write!(
+                    f,
+                    "Region {width}x{height} at ({x}, {y}) is outside the image"
+                )
+            }
+            Error::LimitExceeded(e) => write!(f, "Image exceeds the {e} limit"),
+            Error::InvalidDimensions { width, len } => {
+                write!(f, "{len} pixels don't make whole rows of width {width}")
+            }
+            Error::ImageTooLarge { width, height } => {
+                write!(f, "A {width}x{height} image is too large for a bitmap")
             }
         }
     }
 }
 
-#[derive(Default, Clone, Debug)]
-pub struct Rgb {
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
+// Inconsistencies that don't prevent decoding
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    FileSizeMismatch { declared: u32, actual: usize },
+    // The pixel data was assumed to follow the palette
+    MissingDataOffset,
+    DataOffsetOverlapsHeaders { offset: u32, headers_end: usize },
+    // The reserved fields of the file header should be zero
+    NonzeroReserved(u32),
+    // Bytes left after the pixel data and the color profile
+    TrailingData(usize),
+    // biSizeImage doesn't match the bytes of pixel data decoded
+    ImageSizeMismatch { declared: u32, actual: usize },
+    // Recovery parsed an invalid info header size as a plain info header
+    HeaderSizeRepaired { declared: u32, used: u32 },
+    // Recovery filled the last rows in file order, the first of them may
+    // be partly decoded
+    MissingRows(u32),
+    // Recovery dropped a color profile of this size cut off by the end of
+    // the file
+    TruncatedProfile(u32),
 }
 
-impl Rgb {
-    pub fn new(r: u8, g: u8, b: u8) -> Self {
-        Self { r, g, b }
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::FileSizeMismatch { declared, actual } => {
+                write!(
+                    f,
+                    "File size is {actual}, but the header declares {declared}"
+                )
+            }
+            Warning::MissingDataOffset => write!(f, "Pixel data offset is missing"),
+            Warning::DataOffsetOverlapsHeaders {
+                offset,
+                headers_end,
+            } => write!(
+                f,
+                "Pixel data offset {offset} overlaps the headers ending at {headers_end}"
+            ),
+            Warning::NonzeroReserved(e) => write!(f, "Reserved header field is {e:#x}"),
+            Warning::TrailingData(e) => write!(f, "{e} bytes follow the pixel data"),
+            Warning::ImageSizeMismatch { declared, actual } => write!(
+                f,
+                "Pixel data is {actual} bytes, but the header declares {declared}"
+            ),
+            Warning::HeaderSizeRepaired { declared, used } => {
+                write!(f, "Info header size {declared} was read as {used}")
+            }
+            Warning::MissingRows(e) => write!(f, "{e} rows are missing and were filled"),
+            Warning::TruncatedProfile(e) => {
+                write!(f, "Color profile of {e} bytes is cut off and was dropped")
+            }
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct RgbImage {
-    pub pixels: Vec<Rgb>,
-    pub width: u32,
+pub(crate) const BI_RGB: u32 = 0;
+pub(crate) const BI_RLE8: u32 = 1;
+pub(crate) const BI_RLE4: u32 = 2;
+pub(crate) const BI_BITFIELDS: u32 = 3;
+pub(crate) const BI_ALPHABITFIELDS: u32 = 6;
+
+// Order in which rows are stored in the file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RowOrder {
+    #[default]
+    BottomUp,
+    TopDown,
 }
 
-impl RgbImage {
-    pub fn new(pixels: Vec<Rgb>, width: u32) -> Self {
-        Self { pixels, width }
-    }
-
-    pub fn save_bmp(&self, file_path: &str) -> Result<(), Error> {
-        let width = self.width;
-        let len = self.pixels.len() as u32;
-
-        let header_size = 14;
-        let info_header_size = 40;
-        let padding = (4 - ((width * 3) % 4)) % 4;
-        let height = len / width;
-        let file_size = header_size + info_header_size + height * padding + len * 3;
-        let data_offset = header_size + info_header_size;
-        let mut buff = Vec::with_capacity(file_size as usize);
-
-        // Header
-        write_u8(&mut buff, 'B' as u8);
-        write_u8(&mut buff, 'M' as u8);
-        write_u32(&mut buff, file_size);
-        write_u32(&mut buff, 0); // unused
-        write_u32(&mut buff, data_offset);
-
-        //InfoHeader
-        write_u32(&mut buff, info_header_size);
-        write_u32(&mut buff, width);
-        write_u32(&mut buff, height);
-        write_u16(&mut buff, 1); // planes
-        write_u16(&mut buff, 24); // bits per pixel
-        write_u32(&mut buff, 0); // compression  0=no compression
-        write_u32(&mut buff, 0); // compressed size, 0=no compression
-        write_u32(&mut buff, width); // horizontal pixel/meter
-        write_u32(&mut buff, height); // vertical pixel/meter
-        write_u32(&mut buff, 16777216); // used colors, 2^24
-        write_u32(&mut buff, 0); // important colors, 0=all
-
-        // Pixels
-        for i in 0..height {
-            let i = height - i - 1;
-            for j in 0..width {
-                let index = (i * width + j) as usize;
-                write_u8(&mut buff, self.pixels[index].b);
-                write_u8(&mut buff, self.pixels[index].g);
-                write_u8(&mut buff, self.pixels[index].r);
-            }
+// Physical resolution in pixels per meter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub x: u32,
+    pub y: u32,
+}
+
+const METERS_PER_INCH: f64 = 0.0254;
+
+impl Resolution {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn from_dpi(x: f64, y: f64) -> Self {
+        Self {
+            x: (x / METERS_PER_INCH).round() as u32,
+            y: (y / METERS_PER_INCH).round() as u32,
+        }
+    }
+
+    pub fn dpi(&self) -> (f64, f64) {
+        (
+            self.x as f64 * METERS_PER_INCH,
+            self.y as f64 * METERS_PER_INCH,
+        )
+    }
+}
+
+impl Default for Resolution {
+    // 72 DPI
+    fn default() -> Self {
+        Self { x: 2835, y: 2835 }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Image<P> {
+    // Always whole rows of width pixels
+    pub(crate) pixels: Vec<P>,
+    pub(crate) width: u32,
+    pub resolution: Resolution,
+}
+
+pub type RgbImage = Image<Rgb>;
+pub type RgbaImage = Image<Rgba>;
+pub type GrayImage = Image<Luma>;
+pub type Gray16Image = Image<Luma<u16>>;
+pub type Rgb16Image = Image<Rgb<u16>>;
+pub type Rgb32FImage = Image<Rgb<f32>>;
+
+impl<P: Pixel> Image<P> {
+    // Panics when the pixels don't make whole rows, see try_new
+    pub fn new(pixels: Vec<P>, width: u32) -> Self {
+        Self::try_new(pixels, width).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn try_new(pixels: Vec<P>, width: u32) -> Result<Self, Error> {
+        check_dimensions(width, pixels.len())?;
+
+        Ok(Self {
+            pixels,
+            width,
+            resolution: Resolution::default(),
+        })
+    }
+
+    // Calls f with the x and y of every pixel, row by row from the top
+    pub fn from_fn(width: u32, height: u32, mut f: impl FnMut(u32, u32) -> P) -> Self {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+        Self::new(pixels, width)
+    }
+
+    pub fn filled(width: u32, height: u32, color: P) -> Self {
+        Self::new(vec![color; width as usize * height as usize], width)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.pixels
+            .len()
+            .checked_div(self.width as usize)
+            .unwrap_or(0) as u32
+    }
+
+    // Row by row from the top
+    pub fn pixels(&self) -> &[P] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [P] {
+        &mut self.pixels
+    }
+
+    pub fn into_pixels(self) -> Vec<P> {
+        self.pixels
+    }
+
+    // Converts every pixel through linear light, so 16 bit and float
+    // images keep their precision and grayscale gets proper luminance
+    pub fn convert<Q: Pixel>(&self) -> Image<Q> {
+        Image {
+            pixels: self.pixels.iter().map(Pixel::convert).collect(),
+            width: self.width,
+            resolution: self.resolution,
+        }
+    }
+
+    // Writes P::encoder_options(), 24 bpp for RGB, 32 bpp with alpha for
+    // RGBA and 8 bpp grays for grayscale
+    pub fn write_bmp<W: Write>(&self, writer: W) -> Result<(), Error> {
+        self.write_bmp_with(writer, &P::encoder_options())
+    }
+
+    pub fn write_bmp_with<W: Write>(
+        &self,
+        writer: W,
+        options: &BmpEncoderOptions,
+    ) -> Result<(), Error> {
+        self.as_view().write_bmp_with(writer, options)
+    }
+
+    pub fn save_bmp<T: AsRef<Path>>(&self, file_path: T) -> Result<(), Error> {
+        self.save_bmp_with(file_path, &P::encoder_options())
+    }
+
+    pub fn save_bmp_with_row_order<T: AsRef<Path>>(
+        &self,
+        file_path: T,
+        order: RowOrder,
+    ) -> Result<(), Error> {
+        self.save_bmp_with(file_path, &P::encoder_options().row_order(order))
+    }
 
-            for _ in 0..padding {
-                write_u8(&mut buff, 0);
+    pub fn save_bmp_with<T: AsRef<Path>>(
+        &self,
+        file_path: T,
+        options: &BmpEncoderOptions,
+    ) -> Result<(), Error> {
+        self.write_bmp_with(File::create(file_path)?, options)
+    }
+
+    pub fn read_bmp<R: Read>(reader: R) -> Result<Self, Error> {
+        Ok(Self::read_bmp_with_metadata(reader)?.0)
+    }
+
+    pub fn read_bmp_with_metadata<R: Read>(reader: R) -> Result<(Self, BmpMetadata), Error> {
+        Self::read_bmp_with(reader, &BmpDecoderOptions::new())
+    }
+
+    pub fn read_bmp_with<R: Read>(
+        reader: R,
+        options: &BmpDecoderOptions,
+    ) -> Result<(Self, BmpMetadata), Error> {
+        // the decoded pixels stay alive while they're converted
+        let pixel_size = size_of::<Rgba>() + size_of::<P>();
+        let (info, pixels) = read(reader, options, pixel_size)?;
+        let image = Self {
+            pixels: pixels.iter().map(P::from_rgba8).collect(),
+            width: info.width,
+            resolution: info.resolution,
+        };
+
+        Ok((image, info.metadata))
+    }
+
+    pub fn load_bmp<T: AsRef<Path>>(file_path: T) -> Result<Self, Error> {
+        Self::read_bmp(File::open(file_path)?)
+    }
+
+    pub fn load_bmp_with_metadata<T: AsRef<Path>>(
+        file_path: T,
+    ) -> Result<(Self, BmpMetadata), Error> {
+        Self::read_bmp_with_metadata(File::open(file_path)?)
+    }
+
+    pub fn load_bmp_with<T: AsRef<Path>>(
+        file_path: T,
+        options: &BmpDecoderOptions,
+    ) -> Result<(Self, BmpMetadata), Error> {
+        Self::read_bmp_with(File::open(file_path)?, options)
+    }
+}
+
+// Empty images are fine in memory, encoding rejects them
+pub(crate) fn check_dimensions(width: u32, len: usize) -> Result<(), Error> {
+    let valid = match width as usize {
+        0 => len == 0,
+        width => len.is_multiple_of(width) && len / width <= u32::MAX as usize,
+    };
+    if !valid {
+        return Err(Error::InvalidDimensions { width, len });
+    }
+
+    Ok(())
+}
+
+// pixel_size is the bytes each pixel takes once decoded, counting every
+// buffer holding it, which is what the allocation limit is checked against
+fn read<R: Read>(
+    mut reader: R,
+    options: &BmpDecoderOptions,
+    pixel_size: usize,
+) -> Result<(InfoHeader, Vec<Rgba>), Error> {
+    let mut buff = vec![];
+    reader.read_to_end(&mut buff)?;
+
+    decode(&buff, options, pixel_size)
+}
+
+fn decode(
+    buff: &[u8],
+    options: &BmpDecoderOptions,
+    pixel_size: usize,
+) -> Result<(InfoHeader, Vec<Rgba>), Error> {
+    let Headers {
+        mut info,
+        palette,
+        info_header_offset,
+        data_offset,
+    } = read_headers(buff, buff.len(), options)?;
+    check_alloc(info.width, info.height, pixel_size, &options.limits)?;
+
+    let mut end = 0;
+    let metadata = &mut info.metadata;
+    if let Some(color_space) = metadata.color_space.as_mut() {
+        if let Some(profile) = color_space.profile.as_mut() {
+            let start = info_header_offset + profile.offset as usize;
+            let size = profile.size;
+            match buff.get(start..).and_then(|src| src.get(..size as usize)) {
+                Some(data) => {
+                    profile.data = data.to_vec();
+                    end = start + data.len();
+                }
+                None if options.recover => {
+                    color_space.profile = None;
+                    metadata.warnings.push(Warning::TruncatedProfile(size));
+                }
+                None => {
+                    return Err(Error::Truncated {
+                        offset: start,
+                        needed: size as usize,
+                    })
+                }
             }
         }
+    }
+
+    let mut warnings = std::mem::take(&mut info.metadata.warnings);
+    let src = &buff[data_offset..];
+    let (rest, pixels) = read_pixels(src, &info, &palette, options.fill(), &mut warnings)
+        .map_err(|e| e.locate(buff.len()))?;
+
+    // The size is often wrong or missing and isn't needed to decode
+    let pixel_data_size = src.len() - rest.len();
+    if info.image_size != 0 && info.image_size as usize != pixel_data_size {
+        warnings.push(Warning::ImageSizeMismatch {
+            declared: info.image_size,
+            actual: pixel_data_size,
+        });
+    }
+    info.metadata.warnings = warnings;
+
+    // Bitmap arrays and icons hold more than one bitmap, only plain bitmaps
+    // are expected to end with their pixels
+    end = end.max(buff.len() - rest.len());
+    if &buff[..2] == b"BM" && end < buff.len() {
+        let trailing = buff.len() - end;
+        info.metadata.warnings.push(Warning::TrailingData(trailing));
+    }
+
+    Ok((info, pixels))
+}
 
-        File::create(file_path)?.write_all(buff.as_mut_slice())?;
+pub(crate) struct Headers {
+    pub info: InfoHeader,
+    pub palette: Vec<Rgba>,
+    // Where the info header starts, color profile offsets are relative to it
+    pub info_header_offset: usize,
+    // Where the pixel rows start
+    pub data_offset: usize,
+}
+
+// Parses everything up to the pixel data. buff must hold at least the
+// headers and the palette, file_len is the length of the whole file.
+pub(crate) fn read_headers(
+    buff: &[u8],
+    file_len: usize,
+    options: &BmpDecoderOptions,
+) -> Result<Headers, Error> {
+    let locate = |e: Error| e.locate(buff.len());
+    let mut header_offset = 0;
+    let (mut src, mut header) = read_header(buff).map_err(locate)?;
+    let mut warnings = vec![];
+    if &header.signature == b"BM" && header.file_size != 0 && header.file_size as usize != file_len
+    {
+        warnings.push(Warning::FileSizeMismatch {
+            declared: header.file_size,
+            actual: file_len,
+        });
+    }
+    if &header.signature == b"BM" && header.reserved != 0 {
+        warnings.push(Warning::NonzeroReserved(header.reserved));
+    }
 
-        Ok(())
+    // Bitmap arrays wrap a list of images, only the first one is decoded
+    if &header.signature == b"BA" {
+        header_offset = buff.len() - src.len();
+        (src, header) = read_header(src).map_err(locate)?;
     }
 
-    pub fn load_bmp(file_path: &str) -> Result<Self, Error> {
+    let mut info_header_offset = buff.len() - src.len();
+    let (src, mut info) = read_info_header(src, options.recover).map_err(locate)?;
+    let (mut src, mut palette) = read_palette(src, &info).map_err(locate)?;
+
+    // Color icons and pointers start with a monochrome mask, the color
+    // bitmap follows with its own headers
+    if matches!(&header.signature, b"CI" | b"CP") {
+        header_offset = buff.len() - src.len();
+        let (next, color_header) = read_header(src).map_err(locate)?;
+        info_header_offset = buff.len() - next.len();
+        let (next, color_info) = read_info_header(next, options.recover).map_err(locate)?;
+        let (next, color_palette) = read_palette(next, &color_info).map_err(locate)?;
+        src = next;
+        header = color_header;
+        info = color_info;
+        palette = color_palette;
+    }
+
+    let headers_end = buff.len() - src.len();
+    let mut data_offset = pixel_data_offset(
+        header.data_offset,
+        header_offset + 10,
+        headers_end,
+        file_len,
+        &mut warnings,
+    )?;
+
+    // Monochrome icons and pointers stack the AND mask on top of the XOR
+    // mask, keep the XOR mask. It's the bottom half, so it comes first
+    // unless the rows are top-down.
+    if matches!(&header.signature, b"IC" | b"PT") {
+        info.height /= 2;
+    }
+    check_limits(&info, &options.limits)?;
+    if matches!(&header.signature, b"IC" | b"PT") && info.top_down {
+        let mask_size = row_size(&info).saturating_mul(info.height as usize);
+        if mask_size > file_len - data_offset {
+            return Err(Error::Truncated {
+                offset: data_offset,
+                needed: mask_size,
+            });
+        }
+        data_offset += mask_size;
+    }
+
+    warnings.append(&mut info.metadata.warnings);
+    info.metadata.warnings = warnings;
+
+    Ok(Headers {
+        info,
+        palette,
+        info_header_offset,
+        data_offset,
+    })
+}
+
+// Only the dimensions, decoding a region at a time needs far less memory
+// than the whole image, see check_alloc
+fn check_limits(info: &InfoHeader, limits: &Limits) -> Result<(), Error> {
+    if info.width > limits.max_width {
+        return Err(Error::LimitExceeded("width"));
+    }
+    if info.height > limits.max_height {
+        return Err(Error::LimitExceeded("height"));
+    }
+
+    Ok(())
+}
+
+// Checks decoding width x height pixels of pixel_size bytes at once stays
+// within the limits
+pub(crate) fn check_alloc(
+    width: u32,
+    height: u32,
+    pixel_size: usize,
+    limits: &Limits,
+) -> Result<(), Error> {
+    let pixels = width as u64 * height as u64;
+    if pixels > limits.max_pixels {
+        return Err(Error::LimitExceeded("total pixels"));
+    }
+    let bytes = pixels.checked_mul(pixel_size as u64);
+    if bytes.is_none_or(|bytes| bytes > limits.max_alloc || bytes > isize::MAX as u64) {
+        return Err(Error::LimitExceeded("allocation"));
+    }
+
+    Ok(())
+}
+
+// Number of pixels in the image, check_alloc makes sure it fits
+pub(crate) fn pixel_count(info: &InfoHeader) -> usize {
+    info.width as usize * info.height as usize
+}
+
+// Resolves the declared pixel data offset, field_offset is where it's
+// stored and headers_end is where the headers and the palette end
+fn pixel_data_offset(
+    data_offset: u32,
+    field_offset: usize,
+    headers_end: usize,
+    file_len: usize,
+    warnings: &mut Vec<Warning>,
+) -> Result<usize, Error> {
+    if data_offset == 0 {
+        warnings.push(Warning::MissingDataOffset);
+        return Ok(headers_end);
+    }
+
+    if (data_offset as usize) < headers_end {
+        warnings.push(Warning::DataOffsetOverlapsHeaders {
+            offset: data_offset,
+            headers_end,
+        });
+    }
+
+    if data_offset as usize > file_len {
+        return Err(Error::InvalidDataOffset {
+            offset: field_offset,
+            field: "bfOffBits",
+            value: data_offset,
+        });
+    }
+
+    Ok(data_offset as usize)
+}
+
+pub(crate) struct InfoHeader {
+    pub width: u32,
+    pub height: u32,
+    pub top_down: bool,
+    pub bits_per_pixel: u16,
+    pub compression: u32,
+    pub resolution: Resolution,
+    pub colors_used: u32,
+    // biSizeImage, 0 when not given
+    pub image_size: u32,
+    // 3 byte RGBTRIPLEs for core headers, 4 byte RGBQUADs otherwise
+    pub palette_entry_size: usize,
+    pub masks: ChannelMasks,
+    pub metadata: BmpMetadata,
+}
+
+// Header fields that don't affect decoding but may matter to the caller
+#[derive(Clone, Debug)]
+pub struct BmpMetadata {
+    pub header_size: u32,
+    // Only set for 16 and 32 bpp images
+    pub masks: Option<ChannelMasks>,
+    // Only set for V4 and V5 headers
+    pub color_space: Option<ColorSpace>,
+    pub warnings: Vec<Warning>,
+}
+
+// What the headers say about a bitmap, read without touching the pixels
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BmpInfo {
+    pub width: u32,
+    pub height: u32,
+    pub top_down: bool,
+    pub bits_per_pixel: u16,
+    pub compression: Compression,
+    pub header_size: u32,
+    // None for the OS/2, V2 and V3 headers
+    pub header_version: Option<HeaderVersion>,
+    // Palette entries, 0 above 8 bpp
+    pub palette_size: u32,
+    pub resolution: Resolution,
+}
+
+// Headers of every bitmap variant fit, including icons with their mask
+// headers and palette
+const INFO_PREFIX: u64 = 512;
+
+impl BmpInfo {
+    pub fn from_bytes(buff: &[u8]) -> Result<Self, Error> {
+        Self::parse(buff).map_err(|e| e.locate(buff.len()))
+    }
+
+    fn parse(buff: &[u8]) -> Result<Self, Error> {
+        let (mut src, mut header) = read_header(buff)?;
+        if &header.signature == b"BA" {
+            (src, header) = read_header(src)?;
+        }
+
+        let (mut src, mut info) = read_info_header(src, false)?;
+        if matches!(&header.signature, b"CI" | b"CP") {
+            let mask_palette = palette_len(&info) as usize * info.palette_entry_size;
+            (src, _) = read_bytes(src, mask_palette)?;
+            (src, _) = read_header(src)?;
+            (_, info) = read_info_header(src, false)?;
+        }
+        if matches!(&header.signature, b"IC" | b"PT") {
+            info.height /= 2;
+        }
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            top_down: info.top_down,
+            bits_per_pixel: info.bits_per_pixel,
+            compression: compression(info.compression),
+            header_size: info.metadata.header_size,
+            header_version: HeaderVersion::from_size(info.metadata.header_size),
+            palette_size: palette_len(&info),
+            resolution: info.resolution,
+        })
+    }
+
+    // Reads only the first few hundred bytes
+    pub fn read<R: Read>(reader: R) -> Result<Self, Error> {
         let mut buff = vec![];
-        File::open(file_path)?.read_to_end(&mut buff)?;
+        reader.take(INFO_PREFIX).read_to_end(&mut buff)?;
 
-        let src = read_header(&buff)?;
-        let (src, width, height) = read_info_header(src)?;
-        let (_, pixels) = read_pixels(src, width, height)?;
+        Self::from_bytes(&buff)
+    }
 
-        Ok(Self { pixels, width })
+    pub fn load<P: AsRef<Path>>(file_path: P) -> Result<Self, Error> {
+        Self::read(File::open(file_path)?)
     }
 }
 
-fn read_header(src: &[u8]) -> Result<&[u8], Error> {
-    dbg!(&src[..14]);
-    let (src, letter_b) = read_u8(src)?;
-    let (src, letter_m) = read_u8(src)?;
-    let (src, _file_size) = read_u32(src)?;
-    let (src, _reserved) = read_u32(src)?;
-    let (src, _data_offset) = read_u32(src)?;
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelMasks {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+    pub alpha: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ColorSpace {
+    pub color_space_type: ColorSpaceType,
+    // CIE XYZ coordinates of the red, green and blue endpoints
+    pub endpoints: [CieXyz; 3],
+    // Red, green and blue gamma
+    pub gamma: [f64; 3],
+    // Only set for V5 headers
+    pub intent: Option<RenderingIntent>,
+    pub profile: Option<ColorProfile>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpaceType {
+    CalibratedRgb,
+    Srgb,
+    WindowsColorSpace,
+    ProfileLinked,
+    ProfileEmbedded,
+    Unknown(u32),
+}
 
-    if letter_b as char != 'B' {
-        return Err(Error::InvalidSignature);
+impl From<u32> for ColorSpaceType {
+    fn from(v: u32) -> Self {
+        match v {
+            0 => Self::CalibratedRgb,
+            0x7352_4742 => Self::Srgb,              // 'sRGB'
+            0x5769_6e20 => Self::WindowsColorSpace, // 'Win '
+            0x4c49_4e4b => Self::ProfileLinked,     // 'LINK'
+            0x4d42_4544 => Self::ProfileEmbedded,   // 'MBED'
+            v => Self::Unknown(v),
+        }
     }
-    if letter_m as char != 'M' {
-        return Err(Error::InvalidSignature);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CieXyz {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Business,
+    Graphics,
+    Images,
+    AbsoluteColorimetric,
+    Unknown(u32),
+}
+
+impl From<u32> for RenderingIntent {
+    fn from(v: u32) -> Self {
+        match v {
+            1 => Self::Business,
+            2 => Self::Graphics,
+            4 => Self::Images,
+            8 => Self::AbsoluteColorimetric,
+            v => Self::Unknown(v),
+        }
     }
+}
 
-    Ok(src)
+#[derive(Clone, Debug)]
+pub struct ColorProfile {
+    // Offset from the start of the info header
+    pub offset: u32,
+    pub size: u32,
+    // The ICC profile itself, or a file name for linked profiles
+    pub data: Vec<u8>,
 }
 
-fn read_info_header(src: &[u8]) -> Result<(&[u8], u32, u32), Error> {
-    dbg!(&src[..40]);
-    let (src, header_size) = read_u32(src)?;
-    let (src, width) = read_u32(src)?;
-    let (src, height) = read_u32(src)?;
-    let (src, planes) = read_u16(src)?;
-    let (src, bits_per_pixel) = read_u16(src)?;
-    let (src, compression) = read_u32(src)?;
-    let (src, _file_size) = read_u32(src)?;
-    let (src, _horiz_pixel_per_meter) = read_u32(src)?;
-    let (src, _vert_pixel_per_meter) = read_u32(src)?;
-    let (src, _colors_used) = read_u32(src)?;
-    let (src, _important_colors) = read_u32(src)?;
+impl ChannelMasks {
+    // Used for 16 and 32 bpp images without BI_BITFIELDS
+    pub(crate) fn default_for(bits_per_pixel: u16) -> Self {
+        match bits_per_pixel {
+            16 => Self {
+                red: 0x7c00,
+                green: 0x03e0,
+                blue: 0x001f,
+                alpha: 0,
+            },
+            _ => Self {
+                red: 0x00ff_0000,
+                green: 0x0000_ff00,
+                blue: 0x0000_00ff,
+                alpha: 0,
+            },
+        }
+    }
 
-    if header_size != 40 {
-        return Err(Error::InvalidHeaderSize(header_size));
+    pub(crate) fn pack(&self, color: &Rgba) -> u32 {
+        pack_channel(color.r, self.red)
+            | pack_channel(color.g, self.green)
+            | pack_channel(color.b, self.blue)
+            | pack_channel(color.a, self.alpha)
     }
+
+    fn color(&self, pixel: u32) -> Rgba {
+        let alpha = match self.alpha {
+            0 => 255,
+            mask => scale_channel(pixel, mask),
+        };
+
+        Rgba::new(
+            scale_channel(pixel, self.red),
+            scale_channel(pixel, self.green),
+            scale_channel(pixel, self.blue),
+            alpha,
+        )
+    }
+}
+
+// Scales 0..=255 to the width of mask and moves it under the mask
+fn pack_channel(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let max = (mask >> mask.trailing_zeros()) as u64;
+    let value = (value as u64 * max + 127) / 255;
+    (value as u32) << mask.trailing_zeros()
+}
+
+// Extracts the bits under mask and scales them to 0..=255
+fn scale_channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let max = (mask >> mask.trailing_zeros()) as u64;
+    let value = ((pixel & mask) >> mask.trailing_zeros()) as u64;
+    ((value * 255 + max / 2) / max) as u8
+}
+
+struct FileHeader {
+    signature: [u8; 2],
+    file_size: u32,
+    reserved: u32,
+    data_offset: u32,
+}
+
+fn read_header(src: &[u8]) -> Result<(&[u8], FileHeader), Error> {
+    let start = src.len();
+    let (src, letter_1) = read_u8(src)?;
+    let (src, letter_2) = read_u8(src)?;
+    let (src, file_size) = read_u32(src)?;
+    // Icons and pointers keep their hotspot here
+    let (src, reserved) = read_u32(src)?;
+    let (src, data_offset) = read_u32(src)?;
+
+    // BM for windows bitmaps, the rest are OS/2 bitmap arrays, icons and pointers
+    let signature = [letter_1, letter_2];
+    if !matches!(&signature, b"BM" | b"BA" | b"CI" | b"CP" | b"IC" | b"PT") {
+        return Err(Error::InvalidSignature {
+            offset: start,
+            field: "bfType",
+            value: signature,
+        });
+    }
+
+    let header = FileHeader {
+        signature,
+        file_size,
+        reserved,
+        data_offset,
+    };
+
+    Ok((src, header))
+}
+
+// When recovering, unknown header sizes are read as a 40 byte info header
+fn read_info_header(src: &[u8], recover: bool) -> Result<(&[u8], InfoHeader), Error> {
+    // Bytes left at the start of the header, field offsets count down from it
+    let start = src.len();
+    let (_, mut header_size) = read_u32(src)?;
+    let mut warnings = vec![];
+    if !matches!(header_size, 12 | 16 | 40 | 52 | 56 | 64 | 108 | 124) {
+        if !recover {
+            return Err(Error::InvalidHeaderSize {
+                offset: start,
+                field: "biSize",
+                value: header_size,
+            });
+        }
+        warnings.push(Warning::HeaderSizeRepaired {
+            declared: header_size,
+            used: 40,
+        });
+        header_size = 40;
+    }
+
+    let (mut src, header) = read_bytes(src, header_size as usize)?;
+
+    if header_size == 12 {
+        return Ok((src, read_core_header(header, start)?));
+    }
+
+    // The short OS/2 2.x header stops after the bit count, the missing
+    // fields are zero
+    let mut fields = [0; 40];
+    let len = header.len().min(fields.len());
+    fields[..len].copy_from_slice(&header[..len]);
+    let os2 = matches!(header_size, 16 | 64);
+
+    let (next, _) = read_u32(&fields)?;
+    let (next, width) = read_u32(next)?;
+    let (next, height) = read_u32(next)?;
+    let height = height as i32;
+    let (next, planes) = read_u16(next)?;
+    let (next, bits_per_pixel) = read_u16(next)?;
+    let (next, compression) = read_u32(next)?;
+    let (next, image_size) = read_u32(next)?;
+    let (next, horiz_pixel_per_meter) = read_u32(next)?;
+    let (next, vert_pixel_per_meter) = read_u32(next)?;
+    let (next, colors_used) = read_u32(next)?;
+    let (_, _important_colors) = read_u32(next)?;
+
     if planes != 1 {
-        return Err(Error::UnsupportedPlaneCount(planes));
+        return Err(Error::UnsupportedPlaneCount {
+            offset: start - 12,
+            field: "biPlanes",
+            value: planes,
+        });
+    }
+    if !matches!(bits_per_pixel, 1 | 4 | 8 | 16 | 24 | 32) {
+        return Err(Error::UnsupportedColorDepth {
+            offset: start - 14,
+            field: "biBitCount",
+            value: bits_per_pixel,
+        });
+    }
+    // OS/2 uses 3 and 4 for huffman and RLE24, neither is supported
+    match (compression, bits_per_pixel) {
+        (BI_RGB, _) | (BI_RLE8, 8) | (BI_RLE4, 4) => {}
+        (BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) if !os2 => {}
+        _ => {
+            return Err(Error::UnsupportedCompression {
+                offset: start - 16,
+                field: "biCompression",
+                value: compression,
+            })
+        }
+    }
+    if bits_per_pixel <= 8 && colors_used > 1 << bits_per_pixel {
+        return Err(Error::InvalidPaletteSize {
+            offset: start - 32,
+            field: "biClrUsed",
+            value: colors_used,
+        });
+    }
+
+    // V2 and later headers carry the masks themselves, a plain info header
+    // is followed by them when bitfields are used
+    let (mut mask_src, mask_count) = match (header_size, compression) {
+        (52, _) => (&header[40..], 3),
+        (56 | 108 | 124, _) => (&header[40..], 4),
+        (40, BI_BITFIELDS) => (src, 3),
+        (40, BI_ALPHABITFIELDS) => (src, 4),
+        _ => (src, 0),
+    };
+    let mut mask_values = [0; 4];
+    for mask in mask_values.iter_mut().take(mask_count) {
+        let (next, value) = read_u32(mask_src)?;
+        *mask = value;
+        mask_src = next;
+    }
+    if header_size == 40 {
+        src = mask_src;
+    }
+
+    let masks = match compression {
+        BI_BITFIELDS | BI_ALPHABITFIELDS => ChannelMasks {
+            red: mask_values[0],
+            green: mask_values[1],
+            blue: mask_values[2],
+            alpha: mask_values[3],
+        },
+        _ => ChannelMasks::default_for(bits_per_pixel),
+    };
+
+    let mut color_space = None;
+    if header_size >= 108 {
+        let (_, cs) = read_color_space(&header[56..], header_size)?;
+        color_space = Some(cs);
     }
-    if bits_per_pixel != 24 {
-        return Err(Error::UnsupportedColorDepth(bits_per_pixel));
+
+    let metadata = BmpMetadata {
+        header_size,
+        masks: (bits_per_pixel > 8).then(|| masks.clone()),
+        color_space,
+        warnings,
+    };
+
+    // 0 means the resolution isn't specified
+    let resolution = match (horiz_pixel_per_meter, vert_pixel_per_meter) {
+        (0, _) | (_, 0) => Resolution::default(),
+        (x, y) => Resolution::new(x, y),
+    };
+
+    let info = InfoHeader {
+        width,
+        // a negative height means the rows are stored top to bottom
+        height: height.unsigned_abs(),
+        top_down: height < 0,
+        bits_per_pixel,
+        compression,
+        resolution,
+        colors_used,
+        image_size,
+        palette_entry_size: 4,
+        masks,
+        metadata,
+    };
+
+    Ok((src, info))
+}
+
+// OS/2 1.x and Windows 2.x BITMAPCOREHEADER, with 16 bit dimensions
+fn read_core_header(header: &[u8], start: usize) -> Result<InfoHeader, Error> {
+    let (src, header_size) = read_u32(header)?;
+    let (src, width) = read_u16(src)?;
+    let (src, height) = read_u16(src)?;
+    let (src, planes) = read_u16(src)?;
+    let (_, bits_per_pixel) = read_u16(src)?;
+
+    if planes != 1 {
+        return Err(Error::UnsupportedPlaneCount {
+            offset: start - 8,
+            field: "bcPlanes",
+            value: planes,
+        });
     }
-    if compression != 0 {
-        return Err(Error::UnsupportedCompression(compression));
+    if !matches!(bits_per_pixel, 1 | 4 | 8 | 24) {
+        return Err(Error::UnsupportedColorDepth {
+            offset: start - 10,
+            field: "bcBitCount",
+            value: bits_per_pixel,
+        });
     }
 
-    Ok((src, width, height))
+    let info = InfoHeader {
+        width: width as u32,
+        height: height as u32,
+        top_down: false,
+        bits_per_pixel,
+        compression: BI_RGB,
+        resolution: Resolution::default(),
+        colors_used: 0,
+        image_size: 0,
+        palette_entry_size: 3,
+        masks: ChannelMasks::default_for(bits_per_pixel),
+        metadata: BmpMetadata {
+            header_size,
+            masks: None,
+            color_space: None,
+            warnings: vec![],
+        },
+    };
+
+    Ok(info)
 }
 
-fn read_pixels(mut src: &[u8], width: u32, height: u32) -> Result<(&[u8], Vec<Rgb>), Error> {
-    let padding = (4 - ((width * 3) % 4)) % 4;
+// Reads the V4 color space fields and the V5 intent and profile fields
+fn read_color_space(src: &[u8], header_size: u32) -> Result<(&[u8], ColorSpace), Error> {
+    let (mut src, color_space_type) = read_u32(src)?;
 
-    let mut pixels = Vec::with_capacity((width * height) as usize);
-    pixels.resize((width * height) as usize, Rgb::default());
+    let mut endpoints = [CieXyz::default(); 3];
+    for endpoint in endpoints.iter_mut() {
+        let (next, x) = read_u32(src)?;
+        let (next, y) = read_u32(next)?;
+        let (next, z) = read_u32(next)?;
+        *endpoint = CieXyz {
+            x: fixed_2_30(x),
+            y: fixed_2_30(y),
+            z: fixed_2_30(z),
+        };
+        src = next;
+    }
 
-    for i in 0..height {
-        let i = height - i - 1;
-        for j in 0..width {
-            let index = (i * width + j) as usize;
-            let (next, b) = read_u8(src)?;
-            let (next, g) = read_u8(next)?;
-            let (next, r) = read_u8(next)?;
-            pixels[index] = Rgb::new(r, g, b);
-            src = next;
+    let mut gamma = [0.0; 3];
+    for g in gamma.iter_mut() {
+        let (next, value) = read_u32(src)?;
+        *g = value as f64 / 65536.0;
+        src = next;
+    }
+
+    let mut color_space = ColorSpace {
+        color_space_type: color_space_type.into(),
+        endpoints,
+        gamma,
+        intent: None,
+        profile: None,
+    };
+
+    if header_size >= 124 {
+        let (next, intent) = read_u32(src)?;
+        let (next, offset) = read_u32(next)?;
+        let (next, size) = read_u32(next)?;
+        let (next, _reserved) = read_u32(next)?;
+        src = next;
+
+        color_space.intent = Some(intent.into());
+        let has_profile = matches!(
+            color_space.color_space_type,
+            ColorSpaceType::ProfileLinked | ColorSpaceType::ProfileEmbedded
+        );
+        // the profile usually follows the pixel data, it's read separately
+        if has_profile && size > 0 {
+            color_space.profile = Some(ColorProfile {
+                offset,
+                size,
+                data: vec![],
+            });
         }
+    }
 
-        for _ in 0..padding {
-            let (next, _) = read_u8(src)?;
-            src = next;
+    Ok((src, color_space))
+}
+
+fn fixed_2_30(v: u32) -> f64 {
+    v as i32 as f64 / (1 << 30) as f64
+}
+
+fn read_palette<'a>(mut src: &'a [u8], info: &InfoHeader) -> Result<(&'a [u8], Vec<Rgba>), Error> {
+    let colors = palette_len(info);
+    let mut palette = Vec::with_capacity(colors as usize);
+    for _ in 0..colors {
+        let (next, b) = read_u8(src)?;
+        let (next, g) = read_u8(next)?;
+        let (next, r) = read_u8(next)?;
+        let (next, _reserved) = read_bytes(next, info.palette_entry_size - 3)?;
+        palette.push(Rgba::new(r, g, b, 255));
+        src = next;
+    }
+
+    Ok((src, palette))
+}
+
+// read_info_header made sure colors_used fits the depth
+fn palette_len(info: &InfoHeader) -> u32 {
+    match (info.bits_per_pixel, info.colors_used) {
+        (9.., _) => 0,
+        // 0 colors used means the full palette for the given depth
+        (bits_per_pixel, 0) => 1 << bits_per_pixel,
+        (_, colors_used) => colors_used,
+    }
+}
+
+// With a fill color, pixel data that is cut short or malformed is decoded
+// as far as possible and the rest is filled instead of failing
+pub(crate) fn read_pixels<'a>(
+    src: &'a [u8],
+    info: &InfoHeader,
+    palette: &[Rgba],
+    fill: Option<Rgba>,
+    warnings: &mut Vec<Warning>,
+) -> Result<(&'a [u8], Vec<Rgba>), Error> {
+    match info.compression {
+        BI_RLE8 | BI_RLE4 => read_rle(src, info, palette, fill, warnings),
+        _ => read_rows(src, info, palette, fill, warnings),
+    }
+}
+
+fn read_rows<'a>(
+    mut src: &'a [u8],
+    info: &InfoHeader,
+    palette: &[Rgba],
+    fill: Option<Rgba>,
+    warnings: &mut Vec<Warning>,
+) -> Result<(&'a [u8], Vec<Rgba>), Error> {
+    let width = info.width as usize;
+    let row_size = row_size(info);
+
+    // Fail before allocating when the file is too short for the image
+    let image_size = row_size.saturating_mul(info.height as usize);
+    if image_size > src.len() && fill.is_none() {
+        return Err(Error::Truncated {
+            offset: src.len(),
+            needed: image_size,
+        });
+    }
+
+    let mut pixels = vec![fill.unwrap_or_default(); pixel_count(info)];
+
+    for i in 0..info.height {
+        let row = row_index(info, i) as usize;
+        let out = &mut pixels[row * width..(row + 1) * width];
+        if src.len() < row_size {
+            decode_available(src, 0, out, info, palette)?;
+            warnings.push(Warning::MissingRows(info.height - i));
+            return Ok((&[], pixels));
         }
+
+        let (next, bytes) = read_bytes(src, row_size)?;
+        src = next;
+        decode_pixels(bytes, 0, out, info, palette)?;
     }
 
     Ok((src, pixels))
 }
 
-fn write_u32(buff: &mut Vec<u8>, val: u32) {
-    for b in val.to_le_bytes() {
-        buff.push(b);
+// Decodes the whole pixels src holds, the rest of out is left as it is
+pub(crate) fn decode_available(
+    src: &[u8],
+    bit_offset: u32,
+    out: &mut [Rgba],
+    info: &InfoHeader,
+    palette: &[Rgba],
+) -> Result<(), Error> {
+    let bits = (src.len() as u64 * 8).saturating_sub(bit_offset as u64);
+    let len = out.len().min((bits / info.bits_per_pixel as u64) as usize);
+    decode_pixels(src, bit_offset, &mut out[..len], info, palette)
+}
+
+// Decodes uncompressed pixels into out, src starts at the given bit offset
+// into the row
+pub(crate) fn decode_pixels(
+    src: &[u8],
+    bit_offset: u32,
+    out: &mut [Rgba],
+    info: &InfoHeader,
+    palette: &[Rgba],
+) -> Result<(), Error> {
+    let bits_per_pixel = info.bits_per_pixel as u32;
+
+    for (j, pixel) in out.iter_mut().enumerate() {
+        // in usize, rows of huge images have more bits than a u32 holds
+        let bit = bit_offset as usize + j * bits_per_pixel as usize;
+        let i = bit / 8;
+        *pixel = match bits_per_pixel {
+            16 => info
+                .masks
+                .color(u16::from_le_bytes([src[i], src[i + 1]]) as u32),
+            24 => Rgba::new(src[i + 2], src[i + 1], src[i], 255),
+            32 => info.masks.color(u32::from_le_bytes([
+                src[i],
+                src[i + 1],
+                src[i + 2],
+                src[i + 3],
+            ])),
+            _ => {
+                let shift = 8 - bits_per_pixel - (bit % 8) as u32;
+                let mask = (1 << bits_per_pixel) - 1;
+                let color_index = ((src[i] as u32 >> shift) & mask) as u8;
+                palette_color(palette, color_index)?
+            }
+        };
+    }
+
+    Ok(())
+}
+
+// Maps the i-th row stored in the file to its row in the image, and back
+pub(crate) fn row_index(info: &InfoHeader, i: u32) -> u32 {
+    if info.top_down {
+        i
+    } else {
+        info.height - i - 1
     }
 }
 
-fn write_u16(buff: &mut Vec<u8>, val: u16) {
-    for b in val.to_le_bytes() {
-        buff.push(b);
+pub(crate) fn compression(code: u32) -> Compression {
+    match code {
+        BI_RLE8 | BI_RLE4 => Compression::Rle,
+        BI_BITFIELDS | BI_ALPHABITFIELDS => Compression::Bitfields,
+        _ => Compression::None,
     }
 }
 
-fn write_u8(buff: &mut Vec<u8>, val: u8) {
-    buff.push(val);
+// Rows are padded to a multiple of 4 bytes
+pub(crate) fn row_size(info: &InfoHeader) -> usize {
+    ((info.width as u64 * info.bits_per_pixel as u64).div_ceil(32) * 4) as usize
 }
 
-fn read_u32(mut src: &[u8]) -> Result<(&[u8], u32), Error> {
-    let mut bytes = [0; 4];
-    src.read_exact(&mut bytes)?;
+// Pixels skipped by delta or early end-of-line codes are left transparent black
+fn read_rle<'a>(
+    mut src: &'a [u8],
+    info: &InfoHeader,
+    palette: &[Rgba],
+    fill: Option<Rgba>,
+    warnings: &mut Vec<Warning>,
+) -> Result<(&'a [u8], Vec<Rgba>), Error> {
+    let rle4 = info.compression == BI_RLE4;
+    let mut pixels = vec![Rgba::default(); pixel_count(info)];
+
+    // y counts rows in file order, from the bottom unless top-down
+    let mut x = 0u32;
+    let mut y = 0u32;
+
+    // Runs are decoded in a closure so a recovering decode can tell how far
+    // they got before failing
+    let mut runs = || -> Result<(), Error> {
+        loop {
+            let (next, count) = read_u8(src)?;
+            let (next, value) = read_u8(next)?;
+            src = next;
+
+            match (count, value) {
+                // end of line
+                (0, 0) => {
+                    x = 0;
+                    y = y.saturating_add(1);
+                }
+                // end of bitmap
+                (0, 1) => return Ok(()),
+                // delta
+                (0, 2) => {
+                    let (next, dx) = read_u8(src)?;
+                    let (next, dy) = read_u8(next)?;
+                    src = next;
+                    x = x.saturating_add(dx as u32);
+                    y = y.saturating_add(dy as u32);
+                }
+                // absolute mode, padded to a 2 byte boundary
+                (0, len) => {
+                    let len = len as usize;
+                    let byte_len = if rle4 { len.div_ceil(2) } else { len };
+                    let (next, bytes) = read_bytes(src, byte_len + byte_len % 2)?;
+                    src = next;
+
+                    for i in 0..len {
+                        let color_index = if rle4 {
+                            nibble(bytes[i / 2], i)
+                        } else {
+                            bytes[i]
+                        };
+                        set_rle_pixel(&mut pixels, info, palette, x, y, color_index)?;
+                        x = x.saturating_add(1);
+                    }
+                }
+                // encoded run
+                (len, color_index) => {
+                    for i in 0..len as usize {
+                        let color_index = if rle4 {
+                            nibble(color_index, i)
+                        } else {
+                            color_index
+                        };
+                        set_rle_pixel(&mut pixels, info, palette, x, y, color_index)?;
+                        x = x.saturating_add(1);
+                    }
+                }
+            }
+        }
+    };
+
+    match (runs(), fill) {
+        (Ok(()), _) => Ok((src, pixels)),
+        (Err(Error::Truncated { .. } | Error::MalformedRle { .. }), Some(fill)) => {
+            let width = info.width as usize;
+            for row in y..info.height {
+                let start = if row == y { (x as usize).min(width) } else { 0 };
+                let i = row_index(info, row) as usize;
+                pixels[i * width + start..(i + 1) * width].fill(fill.clone());
+            }
+            warnings.push(Warning::MissingRows(info.height.saturating_sub(y)));
+            Ok((&[], pixels))
+        }
+        (Err(e), _) => Err(e),
+    }
+}
+
+fn set_rle_pixel(
+    pixels: &mut [Rgba],
+    info: &InfoHeader,
+    palette: &[Rgba],
+    x: u32,
+    y: u32,
+    color_index: u8,
+) -> Result<(), Error> {
+    if x >= info.width || y >= info.height {
+        return Err(Error::MalformedRle { x, y });
+    }
+
+    let index = row_index(info, y) as usize * info.width as usize + x as usize;
+    pixels[index] = palette_color(palette, color_index)?;
+    Ok(())
+}
+
+// RLE4 packs two pixels per byte, high nibble first
+fn nibble(byte: u8, i: usize) -> u8 {
+    if i.is_multiple_of(2) {
+        byte >> 4
+    } else {
+        byte & 0x0f
+    }
+}
+
+fn palette_color(palette: &[Rgba], color_index: u8) -> Result<Rgba, Error> {
+    palette
+        .get(color_index as usize)
+        .cloned()
+        .ok_or(Error::InvalidPaletteIndex(color_index))
+}
+
+fn read_u32(src: &[u8]) -> Result<(&[u8], u32), Error> {
+    let (src, bytes) = read_bytes(src, 4)?;
     let val = bytes[0] as u32
         | ((bytes[1] as u32) << 8)
         | ((bytes[2] as u32) << 16)
@@ -228,16 +1547,26 @@ fn read_u32(mut src: &[u8]) -> Result<(&[u8], u32), Error> {
     Ok((src, val))
 }
 
-fn read_u16(mut src: &[u8]) -> Result<(&[u8], u16), Error> {
-    let mut bytes = [0; 2];
-    src.read_exact(&mut bytes)?;
+fn read_u16(src: &[u8]) -> Result<(&[u8], u16), Error> {
+    let (src, bytes) = read_bytes(src, 2)?;
 
     Ok((src, bytes[0] as u16 | ((bytes[1] as u16) << 8)))
 }
 
-fn read_u8(mut src: &[u8]) -> Result<(&[u8], u8), Error> {
-    let mut bytes = [0; 1];
-    src.read_exact(&mut bytes)?;
+fn read_bytes(src: &[u8], len: usize) -> Result<(&[u8], &[u8]), Error> {
+    if src.len() < len {
+        return Err(Error::Truncated {
+            offset: src.len(),
+            needed: len,
+        });
+    }
+
+    let (bytes, src) = src.split_at(len);
+    Ok((src, bytes))
+}
+
+fn read_u8(src: &[u8]) -> Result<(&[u8], u8), Error> {
+    let (src, bytes) = read_bytes(src, 1)?;
 
     Ok((src, bytes[0]))
 }
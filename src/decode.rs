@@ -0,0 +1,307 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    bmp::{
+        check_alloc, compression, decode_available, decode_pixels, read_headers, read_pixels,
+        row_index, row_size, Headers,
+    },
+    BmpMetadata, Compression, Error, Resolution, Rgba, Warning, BI_RLE4, BI_RLE8,
+};
+
+// Caps on what the headers of a bitmap may make the decoder allocate,
+// files asking for more fail with Error::LimitExceeded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    // Bytes of decoded pixels
+    pub max_alloc: u64,
+}
+
+impl Limits {
+    pub fn none() -> Self {
+        Self {
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_pixels: u64::MAX,
+            max_alloc: u64::MAX,
+        }
+    }
+}
+
+impl Default for Limits {
+    // 16384 x 16384 pixels, or 1 GiB of decoded pixels
+    fn default() -> Self {
+        Self {
+            max_width: 1 << 20,
+            max_height: 1 << 20,
+            max_pixels: 1 << 28,
+            max_alloc: 1 << 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BmpDecoderOptions {
+    pub(crate) limits: Limits,
+    // Decode what's there of damaged files instead of failing, the way
+    // browsers show partial downloads. Repairs are reported as warnings.
+    pub(crate) recover: bool,
+    // Stands in for the pixels a recovering decode couldn't read
+    fill_color: Rgba,
+}
+
+impl BmpDecoderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn recover(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    pub fn fill_color(mut self, fill_color: Rgba) -> Self {
+        self.fill_color = fill_color;
+        self
+    }
+
+    pub(crate) fn fill(&self) -> Option<Rgba> {
+        self.recover.then(|| self.fill_color.clone())
+    }
+}
+
+// Enough for the largest headers and palettes, even for icons which
+// carry two sets of them
+const HEADERS_PREFIX: u64 = 4096;
+
+// Reads a bitmap lazily from a seekable source. Only the headers are parsed
+// up front, rows and regions of uncompressed bitmaps are read on demand so
+// huge images can be processed a tile at a time. RLE bitmaps can't be
+// indexed and are decoded whole on first access.
+pub struct BmpDecoder<R: Read + Seek> {
+    reader: R,
+    // Where the bitmap starts in the reader
+    base: u64,
+    file_len: u64,
+    headers: Headers,
+    limits: Limits,
+    fill: Option<Rgba>,
+    rle_pixels: Option<Vec<Rgba>>,
+}
+
+impl BmpDecoder<File> {
+    pub fn open<P: AsRef<Path>>(file_path: P) -> Result<Self, Error> {
+        Self::new(File::open(file_path)?)
+    }
+}
+
+impl<R: Read + Seek> BmpDecoder<R> {
+    pub fn new(reader: R) -> Result<Self, Error> {
+        Self::with_options(reader, &BmpDecoderOptions::new())
+    }
+
+    pub fn with_options(mut reader: R, options: &BmpDecoderOptions) -> Result<Self, Error> {
+        let base = reader.stream_position()?;
+        let file_len = reader.seek(SeekFrom::End(0))? - base;
+        reader.seek(SeekFrom::Start(base))?;
+
+        let mut prefix = vec![];
+        reader
+            .by_ref()
+            .take(file_len.min(HEADERS_PREFIX))
+            .read_to_end(&mut prefix)?;
+        let mut headers = read_headers(&prefix, file_len as usize, options)?;
+
+        let info_header_offset = headers.info_header_offset as u64;
+        let metadata = &mut headers.info.metadata;
+        if let Some(color_space) = metadata.color_space.as_mut() {
+            if let Some(profile) = color_space.profile.as_mut() {
+                let start = info_header_offset + profile.offset as u64;
+                let size = profile.size;
+                // Don't trust the size before allocating for it
+                if start + size as u64 <= file_len {
+                    reader.seek(SeekFrom::Start(base + start))?;
+                    profile.data = vec![0; size as usize];
+                    reader.read_exact(&mut profile.data)?;
+                } else if options.recover {
+                    color_space.profile = None;
+                    metadata.warnings.push(Warning::TruncatedProfile(size));
+                } else {
+                    return Err(Error::Truncated {
+                        offset: start as usize,
+                        needed: size as usize,
+                    });
+                }
+            }
+        }
+
+        Ok(Self {
+            reader,
+            base,
+            file_len,
+            headers,
+            limits: options.limits,
+            fill: options.fill(),
+            rle_pixels: None,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.headers.info.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.headers.info.height
+    }
+
+    pub fn bits_per_pixel(&self) -> u16 {
+        self.headers.info.bits_per_pixel
+    }
+
+    pub fn compression(&self) -> Compression {
+        compression(self.headers.info.compression)
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.headers.info.resolution
+    }
+
+    pub fn metadata(&self) -> &BmpMetadata {
+        &self.headers.info.metadata
+    }
+
+    // Reads row y, counted from the top of the image
+    pub fn read_row(&mut self, y: u32) -> Result<Vec<Rgba>, Error> {
+        self.read_region(0, y, self.width(), 1)
+    }
+
+    // Reads a rectangle of the image, in image order from its top left
+    // corner. Only the bytes covering the rectangle are read.
+    pub fn read_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<Rgba>, Error> {
+        let in_bounds = x
+            .checked_add(width)
+            .is_some_and(|right| right <= self.width())
+            && y.checked_add(height)
+                .is_some_and(|bottom| bottom <= self.height());
+        if !in_bounds {
+            return Err(Error::RegionOutOfBounds {
+                x,
+                y,
+                width,
+                height,
+            });
+        }
+
+        check_alloc(width, height, size_of::<Rgba>(), &self.limits)?;
+        if matches!(self.headers.info.compression, BI_RLE8 | BI_RLE4) {
+            return self.read_rle_region(x, y, width, height);
+        }
+
+        let info = &self.headers.info;
+        let bits_per_pixel = info.bits_per_pixel as u64;
+        let first_bit = x as u64 * bits_per_pixel;
+        let first_byte = first_bit / 8;
+        let len = ((x + width) as u64 * bits_per_pixel).div_ceil(8) - first_byte;
+
+        let fill = self.fill.clone().unwrap_or_default();
+        let mut pixels = vec![fill; width as usize * height as usize];
+        let mut bytes = vec![0; len as usize];
+        for (i, out) in pixels.chunks_exact_mut(width.max(1) as usize).enumerate() {
+            let file_row = row_index(info, y + i as u32) as u64;
+            let offset =
+                self.headers.data_offset as u64 + file_row * row_size(info) as u64 + first_byte;
+            let bit_offset = (first_bit % 8) as u32;
+            let palette = &self.headers.palette;
+
+            if offset + len > self.file_len {
+                if self.fill.is_none() {
+                    return Err(Error::Truncated {
+                        offset: offset as usize,
+                        needed: len as usize,
+                    });
+                }
+
+                // decode what's left of a cut short row
+                let available = self.file_len.saturating_sub(offset) as usize;
+                let bytes = &mut bytes[..available];
+                self.reader.seek(SeekFrom::Start(self.base + offset))?;
+                self.reader.read_exact(bytes)?;
+                decode_available(bytes, bit_offset, out, info, palette)?;
+                continue;
+            }
+
+            self.reader.seek(SeekFrom::Start(self.base + offset))?;
+            self.reader.read_exact(&mut bytes)?;
+            decode_pixels(&bytes, bit_offset, out, info, palette)?;
+        }
+
+        Ok(pixels)
+    }
+
+    // Yields the rows from the top of the image down
+    pub fn rows(&mut self) -> impl Iterator<Item = Result<Vec<Rgba>, Error>> + '_ {
+        (0..self.height()).map(|y| self.read_row(y))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn read_rle_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<Rgba>, Error> {
+        if self.rle_pixels.is_none() {
+            // RLE can't be indexed, the whole image is decoded
+            check_alloc(self.width(), self.height(), size_of::<Rgba>(), &self.limits)?;
+            let start = self.base + self.headers.data_offset as u64;
+            self.reader.seek(SeekFrom::Start(start))?;
+            let mut buff = vec![];
+            self.reader.read_to_end(&mut buff)?;
+            let info = &mut self.headers.info;
+            let mut warnings = std::mem::take(&mut info.metadata.warnings);
+            let (_, pixels) = read_pixels(
+                &buff,
+                info,
+                &self.headers.palette,
+                self.fill.clone(),
+                &mut warnings,
+            )
+            .map_err(|e| e.locate(self.headers.data_offset + buff.len()))?;
+            info.metadata.warnings = warnings;
+            self.rle_pixels = Some(pixels);
+        }
+
+        let image_width = self.width() as usize;
+        let pixels = self.rle_pixels.as_deref().unwrap_or_default();
+        let region = (y..y + height)
+            .flat_map(|row| {
+                let start = row as usize * image_width + x as usize;
+                &pixels[start..start + width as usize]
+            })
+            .cloned()
+            .collect();
+
+        Ok(region)
+    }
+}
@@ -0,0 +1,632 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::{
+    quantize::{quantize, Quantized},
+    ChannelMasks, Error, ImageView, Pixel, Quantizer, Resolution, Rgba, RowOrder, BI_BITFIELDS,
+    BI_RGB, BI_RLE4, BI_RLE8,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeaderVersion {
+    // 12 byte BITMAPCOREHEADER, limited to 16 bit dimensions
+    Core,
+    // 40 byte BITMAPINFOHEADER
+    #[default]
+    Info,
+    // 108 byte BITMAPV4HEADER
+    V4,
+    // 124 byte BITMAPV5HEADER
+    V5,
+}
+
+impl HeaderVersion {
+    fn size(self) -> u32 {
+        match self {
+            HeaderVersion::Core => 12,
+            HeaderVersion::Info => 40,
+            HeaderVersion::V4 => 108,
+            HeaderVersion::V5 => 124,
+        }
+    }
+
+    pub(crate) fn from_size(size: u32) -> Option<Self> {
+        match size {
+            12 => Some(HeaderVersion::Core),
+            40 => Some(HeaderVersion::Info),
+            108 => Some(HeaderVersion::V4),
+            124 => Some(HeaderVersion::V5),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    // Explicit channel masks, 5-6-5 for 16 bpp and BGRA for 32 bpp
+    Bitfields,
+    // RLE8 for 8 bpp and RLE4 for 4 bpp
+    Rle,
+}
+
+#[derive(Clone, Debug)]
+pub struct BmpEncoderOptions {
+    bits_per_pixel: u16,
+    header_version: HeaderVersion,
+    row_order: RowOrder,
+    compression: Compression,
+    // Overrides the resolution of the image
+    resolution: Option<Resolution>,
+    quantizer: Quantizer,
+    dither: bool,
+}
+
+impl Default for BmpEncoderOptions {
+    fn default() -> Self {
+        Self {
+            bits_per_pixel: 24,
+            header_version: HeaderVersion::Info,
+            row_order: RowOrder::BottomUp,
+            compression: Compression::None,
+            resolution: None,
+            quantizer: Quantizer::MedianCut,
+            dither: false,
+        }
+    }
+}
+
+impl BmpEncoderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 1, 4 and 8 bpp use an exact palette when the image fits, and the
+    // quantizer otherwise
+    pub fn bits_per_pixel(mut self, bits_per_pixel: u16) -> Self {
+        self.bits_per_pixel = bits_per_pixel;
+        self
+    }
+
+    pub fn header_version(mut self, header_version: HeaderVersion) -> Self {
+        self.header_version = header_version;
+        self
+    }
+
+    pub fn row_order(mut self, row_order: RowOrder) -> Self {
+        self.row_order = row_order;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    pub fn quantizer(mut self, quantizer: Quantizer) -> Self {
+        self.quantizer = quantizer;
+        self
+    }
+
+    // Floyd-Steinberg dithering for quantized images
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    fn validate(&self, width: u32, height: u32) -> Result<(), Error> {
+        let bits_per_pixel = self.bits_per_pixel;
+        if !matches!(bits_per_pixel, 1 | 4 | 8 | 16 | 24 | 32) {
+            return Err(Error::InvalidEncoderOptions(
+                "bits per pixel must be 1, 4, 8, 16, 24 or 32",
+            ));
+        }
+
+        if self.compression == Compression::Bitfields && !matches!(bits_per_pixel, 16 | 32) {
+            return Err(Error::InvalidEncoderOptions(
+                "bitfields compression needs 16 or 32 bpp",
+            ));
+        }
+
+        if self.compression == Compression::Rle {
+            if !matches!(bits_per_pixel, 4 | 8) {
+                return Err(Error::InvalidEncoderOptions(
+                    "RLE compression needs 4 or 8 bpp",
+                ));
+            }
+            if self.row_order == RowOrder::TopDown {
+                return Err(Error::InvalidEncoderOptions(
+                    "RLE compressed bitmaps can't be top-down",
+                ));
+            }
+        }
+
+        if self.header_version == HeaderVersion::Core {
+            if !matches!(bits_per_pixel, 1 | 4 | 8 | 24) {
+                return Err(Error::InvalidEncoderOptions(
+                    "core headers only support 1, 4, 8 and 24 bpp",
+                ));
+            }
+            if self.compression != Compression::None {
+                return Err(Error::InvalidEncoderOptions(
+                    "core headers don't support compression",
+                ));
+            }
+            if self.row_order == RowOrder::TopDown {
+                return Err(Error::InvalidEncoderOptions(
+                    "core headers can't store top-down bitmaps",
+                ));
+            }
+            if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                return Err(Error::InvalidEncoderOptions(
+                    "core headers can't store dimensions above 65535",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn masks(&self) -> ChannelMasks {
+        match (self.compression, self.bits_per_pixel) {
+            (Compression::Bitfields, 16) => ChannelMasks {
+                red: 0xf800,
+                green: 0x07e0,
+                blue: 0x001f,
+                alpha: 0,
+            },
+            // A plain info header can only be followed by the color masks
+            (Compression::Bitfields, _) if self.header_version != HeaderVersion::Info => {
+                ChannelMasks {
+                    alpha: 0xff00_0000,
+                    ..ChannelMasks::default_for(32)
+                }
+            }
+            _ => ChannelMasks::default_for(self.bits_per_pixel),
+        }
+    }
+}
+
+// What's left of bfSize's 32 bits after the largest headers and palette
+const MAX_PIXEL_DATA_SIZE: u64 = u32::MAX as u64 - (14 + 124 + 256 * 4);
+
+// Bytes per row, padded to a multiple of 4, and bytes of all the rows.
+// Fails when the dimensions or sizes don't fit the headers' fields.
+fn pixel_data_size(width: u32, height: u32, bits_per_pixel: u32) -> Result<(u32, u32), Error> {
+    let row_size = (width as u64 * bits_per_pixel as u64).div_ceil(32) * 4;
+    let image_size = row_size * height as u64;
+    if width > i32::MAX as u32 || height > i32::MAX as u32 || image_size > MAX_PIXEL_DATA_SIZE {
+        return Err(Error::ImageTooLarge { width, height });
+    }
+
+    Ok((row_size as u32, image_size as u32))
+}
+
+pub(crate) fn encode<P: Pixel>(
+    image: &ImageView<P>,
+    options: &BmpEncoderOptions,
+) -> Result<Vec<u8>, Error> {
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 {
+        return Err(Error::InvalidDimensions { width, len: 0 });
+    }
+    options.validate(width, height)?;
+    let bits_per_pixel = options.bits_per_pixel as u32;
+    let (row_size, image_size) = pixel_data_size(width, height, bits_per_pixel)?;
+
+    let Quantized { palette, indices } = match bits_per_pixel {
+        1 | 4 | 8 => quantize(
+            image,
+            1 << bits_per_pixel,
+            options.quantizer,
+            options.dither,
+        )?,
+        _ => Quantized {
+            palette: vec![],
+            indices: vec![],
+        },
+    };
+    let resolution = options.resolution.unwrap_or(image.resolution);
+
+    let rle = match options.compression {
+        Compression::Rle => Some(encode_rle(&indices, width, height, bits_per_pixel == 4)),
+        _ => None,
+    };
+    let image_size = match &rle {
+        // runs of single pixels take more room than the plain rows
+        Some(data) if data.len() as u64 > MAX_PIXEL_DATA_SIZE => {
+            return Err(Error::ImageTooLarge { width, height });
+        }
+        Some(data) => data.len() as u32,
+        None => image_size,
+    };
+
+    let mut buff = vec![];
+    write_headers(
+        &mut buff, width, height, &palette, image_size, resolution, options,
+    );
+    buff.reserve(image_size as usize);
+
+    // Pixels
+    if let Some(data) = rle {
+        buff.extend_from_slice(&data);
+        return Ok(buff);
+    }
+
+    let masks = options.masks();
+    let mut row = vec![0; row_size as usize];
+    for i in 0..height {
+        let i = match options.row_order {
+            RowOrder::BottomUp => height - i - 1,
+            RowOrder::TopDown => i,
+        };
+        let start = i as usize * width as usize;
+        let end = start + width as usize;
+        let indices = indices.get(start..end).unwrap_or(&[]);
+        pack_row(&mut row, image.row(i), indices, &masks, bits_per_pixel);
+
+        buff.extend_from_slice(&row);
+    }
+
+    Ok(buff)
+}
+
+// Writes the file header, info header, masks and palette
+fn write_headers(
+    buff: &mut Vec<u8>,
+    width: u32,
+    height: u32,
+    palette: &[Rgba],
+    image_size: u32,
+    resolution: Resolution,
+    options: &BmpEncoderOptions,
+) {
+    let bits_per_pixel = options.bits_per_pixel as u32;
+    let masks = options.masks();
+
+    let header_size = 14;
+    let info_header_size = options.header_version.size();
+    let bitfields = options.compression == Compression::Bitfields;
+    let masks_size = match options.header_version {
+        HeaderVersion::Info if bitfields => 12,
+        _ => 0,
+    };
+    let palette_entry_size = match options.header_version {
+        HeaderVersion::Core => 3,
+        _ => 4,
+    };
+    // core headers have no biClrUsed, readers expect the full table
+    let palette_len = match options.header_version {
+        HeaderVersion::Core if !palette.is_empty() => 1 << bits_per_pixel,
+        _ => palette.len() as u32,
+    };
+    let palette_size = palette_len * palette_entry_size;
+    let data_offset = header_size + info_header_size + masks_size + palette_size;
+    let file_size = data_offset + image_size;
+
+    // Header
+    write_u8(buff, b'B');
+    write_u8(buff, b'M');
+    write_u32(buff, file_size);
+    write_u32(buff, 0); // unused
+    write_u32(buff, data_offset);
+
+    // InfoHeader
+    write_u32(buff, info_header_size);
+    if options.header_version == HeaderVersion::Core {
+        write_u16(buff, width as u16);
+        write_u16(buff, height as u16);
+        write_u16(buff, 1); // planes
+        write_u16(buff, options.bits_per_pixel);
+    } else {
+        write_u32(buff, width);
+        match options.row_order {
+            RowOrder::BottomUp => write_u32(buff, height),
+            // top-down bitmaps store a negative height
+            RowOrder::TopDown => write_u32(buff, (height as i32).wrapping_neg() as u32),
+        }
+        write_u16(buff, 1); // planes
+        write_u16(buff, options.bits_per_pixel);
+        let compression = match (options.compression, bits_per_pixel) {
+            (Compression::None, _) => BI_RGB,
+            (Compression::Bitfields, _) => BI_BITFIELDS,
+            (Compression::Rle, 4) => BI_RLE4,
+            (Compression::Rle, _) => BI_RLE8,
+        };
+        write_u32(buff, compression);
+        write_u32(buff, image_size);
+        write_u32(buff, resolution.x); // horizontal pixel/meter
+        write_u32(buff, resolution.y); // vertical pixel/meter
+        write_u32(buff, palette.len() as u32); // used colors
+        write_u32(buff, 0); // important colors, 0=all
+    }
+
+    if masks_size > 0
+        || matches!(
+            options.header_version,
+            HeaderVersion::V4 | HeaderVersion::V5
+        )
+    {
+        write_u32(buff, masks.red);
+        write_u32(buff, masks.green);
+        write_u32(buff, masks.blue);
+    }
+    if matches!(
+        options.header_version,
+        HeaderVersion::V4 | HeaderVersion::V5
+    ) {
+        write_u32(buff, masks.alpha);
+        write_u32(buff, 0x7352_4742); // color space, 'sRGB'
+        for _ in 0..12 {
+            write_u32(buff, 0); // endpoints and gamma, unused for sRGB
+        }
+    }
+    if options.header_version == HeaderVersion::V5 {
+        write_u32(buff, 4); // rendering intent, LCS_GM_IMAGES
+        write_u32(buff, 0); // profile offset
+        write_u32(buff, 0); // profile size
+        write_u32(buff, 0); // reserved
+    }
+
+    // Palette
+    for color in palette.iter() {
+        write_u8(buff, color.b);
+        write_u8(buff, color.g);
+        write_u8(buff, color.r);
+        if palette_entry_size == 4 {
+            write_u8(buff, 0);
+        }
+    }
+    let padding = palette_len as usize - palette.len();
+    buff.extend(std::iter::repeat_n(
+        0,
+        padding * palette_entry_size as usize,
+    ));
+}
+
+// Packs one row of pixels, or of palette indices for 1, 4 and 8 bpp,
+// including the padding
+fn pack_row<P: Pixel>(
+    row: &mut [u8],
+    pixels: &[P],
+    indices: &[u8],
+    masks: &ChannelMasks,
+    bits_per_pixel: u32,
+) {
+    row.fill(0);
+
+    for (j, pixel) in pixels.iter().enumerate() {
+        match bits_per_pixel {
+            16 => {
+                let value = masks.pack(&pixel.to_rgba8()) as u16;
+                row[j * 2..j * 2 + 2].copy_from_slice(&value.to_le_bytes());
+            }
+            24 => {
+                let pixel = pixel.to_rgba8();
+                row[j * 3..j * 3 + 3].copy_from_slice(&[pixel.b, pixel.g, pixel.r]);
+            }
+            32 => {
+                let value = masks.pack(&pixel.to_rgba8());
+                row[j * 4..j * 4 + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            _ => {
+                // in usize, rows of huge images have more bits than a u32 holds
+                let bit = j * bits_per_pixel as usize;
+                let shift = 8 - bits_per_pixel - (bit % 8) as u32;
+                row[bit / 8] |= indices[j] << shift;
+            }
+        }
+    }
+}
+
+// Streams a bitmap to the writer one row at a time, so only a row is ever
+// held in memory. Rows may arrive in either order, the writer seeks to
+// where each one belongs in the file. Palettized and RLE output need the
+// whole image up front and aren't supported.
+pub struct BmpRowWriter<W: Write + Seek> {
+    writer: W,
+    width: u32,
+    height: u32,
+    // Order the rows are passed in
+    input_order: RowOrder,
+    options: BmpEncoderOptions,
+    masks: ChannelMasks,
+    data_start: u64,
+    row: Vec<u8>,
+    rows_written: u32,
+}
+
+impl<W: Write + Seek> BmpRowWriter<W> {
+    pub fn new(
+        mut writer: W,
+        width: u32,
+        height: u32,
+        input_order: RowOrder,
+        options: &BmpEncoderOptions,
+    ) -> Result<Self, Error> {
+        options.validate(width, height)?;
+        if !matches!(options.bits_per_pixel, 16 | 24 | 32) {
+            return Err(Error::InvalidEncoderOptions(
+                "streaming needs 16, 24 or 32 bpp",
+            ));
+        }
+
+        let bits_per_pixel = options.bits_per_pixel as u32;
+        let (row_size, image_size) = pixel_data_size(width, height, bits_per_pixel)?;
+        let mut header = vec![];
+        let resolution = options.resolution.unwrap_or_default();
+        write_headers(
+            &mut header,
+            width,
+            height,
+            &[],
+            image_size,
+            resolution,
+            options,
+        );
+        writer.write_all(&header)?;
+        let data_start = writer.stream_position()?;
+
+        Ok(Self {
+            writer,
+            width,
+            height,
+            input_order,
+            options: options.clone(),
+            masks: options.masks(),
+            data_start,
+            row: vec![0; row_size as usize],
+            rows_written: 0,
+        })
+    }
+
+    pub fn write_row<P: Pixel>(&mut self, pixels: &[P]) -> Result<(), Error> {
+        if pixels.len() != self.width as usize {
+            return Err(Error::InvalidRowLength {
+                expected: self.width,
+                got: pixels.len(),
+            });
+        }
+        if self.rows_written == self.height {
+            return Err(Error::RowCountMismatch {
+                expected: self.height,
+                got: self.rows_written + 1,
+            });
+        }
+
+        let bits_per_pixel = self.options.bits_per_pixel as u32;
+        pack_row(&mut self.row, pixels, &[], &self.masks, bits_per_pixel);
+
+        // only seek when the rows come in a different order than the file's
+        if self.input_order != self.options.row_order {
+            let file_row = self.height - self.rows_written - 1;
+            let offset = file_row as u64 * self.row.len() as u64;
+            self.writer
+                .seek(SeekFrom::Start(self.data_start + offset))?;
+        }
+        self.writer.write_all(&self.row)?;
+        self.rows_written += 1;
+
+        Ok(())
+    }
+
+    // Checks every row was written and leaves the writer at the end of the
+    // bitmap
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.rows_written != self.height {
+            return Err(Error::RowCountMismatch {
+                expected: self.height,
+                got: self.rows_written,
+            });
+        }
+
+        let image_size = self.height as u64 * self.row.len() as u64;
+        self.writer
+            .seek(SeekFrom::Start(self.data_start + image_size))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+// Encodes rows bottom-up, runs of 3 or more pixels become encoded runs
+// and everything between them absolute mode, shorter stretches are
+// written as single pixel runs since absolute mode needs at least 3
+fn encode_rle(indices: &[u8], width: u32, height: u32, rle4: bool) -> Vec<u8> {
+    let mut data = vec![];
+
+    for i in (0..height).rev() {
+        let start = i as usize * width as usize;
+        let row = &indices[start..start + width as usize];
+        let mut pos = 0;
+
+        while pos < row.len() {
+            let run = rle_run(row, pos, rle4);
+            if run >= 3 {
+                write_u8(&mut data, run as u8);
+                write_u8(&mut data, rle_pair(row, pos, rle4));
+                pos += run;
+                continue;
+            }
+
+            let start = pos;
+            while pos < row.len() && pos - start < 255 && rle_run(row, pos, rle4) < 3 {
+                pos += 1;
+            }
+            let literal = &row[start..pos];
+
+            if literal.len() < 3 {
+                for &index in literal {
+                    write_u8(&mut data, 1);
+                    write_u8(&mut data, if rle4 { index << 4 } else { index });
+                }
+                continue;
+            }
+
+            write_u8(&mut data, 0);
+            write_u8(&mut data, literal.len() as u8);
+            let bytes = if rle4 {
+                literal
+                    .chunks(2)
+                    .map(|pair| pair[0] << 4 | pair.get(1).unwrap_or(&0))
+                    .collect()
+            } else {
+                literal.to_vec()
+            };
+            data.extend_from_slice(&bytes);
+            // absolute runs are padded to a 2 byte boundary
+            if bytes.len() % 2 == 1 {
+                write_u8(&mut data, 0);
+            }
+        }
+
+        // end of line, or end of bitmap after the last row
+        write_u8(&mut data, 0);
+        write_u8(&mut data, if i == 0 { 1 } else { 0 });
+    }
+
+    data
+}
+
+// Length of the run starting at pos, RLE4 runs alternate between two
+// colors so any repeating pair counts
+fn rle_run(row: &[u8], pos: usize, rle4: bool) -> usize {
+    let pair = [row[pos], *row.get(pos + 1).unwrap_or(&row[pos])];
+    let pair = if rle4 { pair } else { [pair[0], pair[0]] };
+
+    row[pos..]
+        .iter()
+        .take(255)
+        .enumerate()
+        .take_while(|(k, &index)| index == pair[k % 2])
+        .count()
+}
+
+fn rle_pair(row: &[u8], pos: usize, rle4: bool) -> u8 {
+    if rle4 {
+        row[pos] << 4 | row.get(pos + 1).unwrap_or(&0)
+    } else {
+        row[pos]
+    }
+}
+
+fn write_u32(buff: &mut Vec<u8>, val: u32) {
+    for b in val.to_le_bytes() {
+        buff.push(b);
+    }
+}
+
+fn write_u16(buff: &mut Vec<u8>, val: u16) {
+    for b in val.to_le_bytes() {
+        buff.push(b);
+    }
+}
+
+fn write_u8(buff: &mut Vec<u8>, val: u8) {
+    buff.push(val);
+}
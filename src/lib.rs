@@ -1,9 +1,66 @@
 mod bmp;
+mod decode;
+mod encode;
+mod pixel;
+mod quantize;
+mod resize;
+mod transform;
+mod view;
 pub use bmp::*;
+pub use decode::{BmpDecoder, BmpDecoderOptions, Limits};
+pub use encode::*;
+pub use pixel::*;
+pub use quantize::Quantizer;
+pub use resize::{Filter, ResizeOptions};
+pub use view::{ImageView, ImageViewMut};
 
 #[cfg(test)]
 mod tests {
-    use crate::{Rgb, RgbImage};
+    use crate::{
+        BmpDecoder, BmpDecoderOptions, BmpEncoderOptions, BmpInfo, BmpRowWriter, ColorSpaceType,
+        Compression, Error, Filter, Gray16Image, GrayImage, HeaderVersion, Image, ImageView,
+        Limits, Luma, Pixel, Quantizer, RenderingIntent, ResizeOptions, Resolution, Rgb,
+        Rgb16Image, Rgb32FImage, RgbImage, Rgba, RgbaImage, RowOrder, Warning,
+    };
+
+    fn info_header(width: i32, height: i32, bpp: u16, compression: u32, colors: u32) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend(40u32.to_le_bytes());
+        header.extend(width.to_le_bytes());
+        header.extend(height.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(bpp.to_le_bytes());
+        header.extend(compression.to_le_bytes());
+        header.extend([0; 8]);
+        header.extend(0u32.to_le_bytes());
+        header.extend(colors.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        header
+    }
+
+    fn bmp_file(info_header: &[u8], palette: &[u8], data: &[u8]) -> Vec<u8> {
+        let data_offset = 14 + info_header.len() + palette.len();
+        let mut file = vec![b'B', b'M'];
+        file.extend(((data_offset + data.len()) as u32).to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend((data_offset as u32).to_le_bytes());
+        file.extend(info_header);
+        file.extend(palette);
+        file.extend(data);
+        file
+    }
+
+    fn load_bytes(bytes: &[u8]) -> Result<RgbImage, Error> {
+        RgbImage::read_bmp(bytes)
+    }
+
+    fn rgb(pixels: &[Rgb]) -> Vec<(u8, u8, u8)> {
+        pixels.iter().map(|p| (p.r, p.g, p.b)).collect()
+    }
+
+    fn to_rgb(pixels: &[Rgba]) -> Vec<Rgb> {
+        pixels.iter().cloned().map(Rgb::from).collect()
+    }
 
     #[test]
     fn save_bmp() {
@@ -32,7 +89,7 @@ mod tests {
         assert!(res.is_ok(), "Error: {}", res.unwrap_err());
 
         let mut pic = res.unwrap();
-        for p in pic.pixels.as_mut_slice() {
+        for p in pic.pixels_mut() {
             p.r = 255 - p.r;
             p.g = 255 - p.g;
             p.b = 255 - p.b;
@@ -41,4 +98,1194 @@ mod tests {
         let res = pic.save_bmp("goodbye.bmp");
         assert!(res.is_ok(), "Error: {}", res.unwrap_err())
     }
+
+    #[test]
+    fn load_indexed() {
+        let palette = [0, 0, 0, 0, 255, 255, 255, 0];
+        // 10x2, rows stored bottom-up, each padded to 4 bytes
+        let data = [
+            0b1010_1010,
+            0b1100_0000,
+            0,
+            0,
+            0b0101_0101,
+            0b0100_0000,
+            0,
+            0,
+        ];
+        let pic = load_bytes(&bmp_file(&info_header(10, 2, 1, 0, 2), &palette, &data)).unwrap();
+        let bits = pic.pixels().iter().map(|p| p.r / 255).collect::<Vec<_>>();
+        assert_eq!(
+            bits,
+            [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1, 0, 1, 1]
+        );
+
+        // biClrUsed limits the palette to 3 entries
+        let palette = [0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0];
+        let data = [0x01, 0x20, 0, 0];
+        let pic = load_bytes(&bmp_file(&info_header(3, 1, 4, 0, 3), &palette, &data)).unwrap();
+        assert_eq!(rgb(pic.pixels()), [(255, 0, 0), (0, 255, 0), (0, 0, 255)]);
+
+        let data = [0x30, 0, 0, 0];
+        let res = load_bytes(&bmp_file(&info_header(1, 1, 4, 0, 3), &palette, &data));
+        assert!(matches!(res, Err(Error::InvalidPaletteIndex(3))));
+    }
+
+    #[test]
+    fn load_rle() {
+        // gray palette, so each pixel's red channel is its color index
+        let palette = [0, 0, 0, 0, 1, 1, 1, 0, 2, 2, 2, 0, 3, 3, 3, 0];
+        let indices = |pic: &RgbImage| pic.pixels().iter().map(|p| p.r).collect::<Vec<_>>();
+
+        #[rustfmt::skip]
+        let data = [
+            2, 1, 0, 3, 1, 2, 3, 0, 0, 0, // bottom row: run, absolute, eol
+            0, 2, 1, 1,                   // delta skips to (1, 2)
+            3, 3, 0, 1,                   // run on the top row, eob
+        ];
+        let header = info_header(5, 3, 8, 1, 4);
+        let pic = load_bytes(&bmp_file(&header, &palette, &data)).unwrap();
+        assert_eq!(indices(&pic), [0, 3, 3, 3, 0, 0, 0, 0, 0, 0, 1, 1, 1, 2, 3]);
+
+        let data = [4, 0x12, 0, 3, 0x32, 0x10, 0, 1];
+        let header = info_header(7, 1, 4, 2, 4);
+        let pic = load_bytes(&bmp_file(&header, &palette, &data)).unwrap();
+        assert_eq!(indices(&pic), [1, 2, 1, 2, 3, 2, 1]);
+
+        let data = [6, 1, 0, 1];
+        let header = info_header(5, 1, 8, 1, 4);
+        let res = load_bytes(&bmp_file(&header, &palette, &data));
+        assert!(matches!(res, Err(Error::MalformedRle { x: 5, y: 0 })));
+    }
+
+    #[test]
+    fn load_bitfields() {
+        // 16 bpp 5-6-5 in a V3 header, which carries the masks itself
+        let mut header = info_header(2, 1, 16, 3, 0);
+        header[..4].copy_from_slice(&56u32.to_le_bytes());
+        for mask in [0xf800u32, 0x07e0, 0x001f, 0] {
+            header.extend(mask.to_le_bytes());
+        }
+        let data = [0x00, 0xf8, 0xe0, 0x07];
+        let pic = load_bytes(&bmp_file(&header, &[], &data)).unwrap();
+        assert_eq!(rgb(pic.pixels()), [(255, 0, 0), (0, 255, 0)]);
+
+        // 32 bpp with the alpha mask following a plain info header
+        let header = info_header(2, 1, 32, 6, 0);
+        let masks = [0xff000000u32, 0x00ff0000, 0x0000ff00, 0x000000ff]
+            .iter()
+            .flat_map(|m| m.to_le_bytes())
+            .collect::<Vec<_>>();
+        let data = [0x80, 0x30, 0x20, 0x10, 0xff, 0x00, 0x00, 0xff];
+        let path = std::env::temp_dir().join("rgba.bmp");
+        std::fs::write(&path, bmp_file(&header, &masks, &data)).unwrap();
+        let pic = RgbaImage::load_bmp(path.to_str().unwrap()).unwrap();
+        let pixels = pic
+            .pixels()
+            .iter()
+            .map(|p| (p.r, p.g, p.b, p.a))
+            .collect::<Vec<_>>();
+        assert_eq!(pixels, [(0x10, 0x20, 0x30, 0x80), (0xff, 0, 0, 0xff)]);
+    }
+
+    #[test]
+    fn load_v5_header() {
+        let mut header = info_header(1, 1, 32, 3, 0);
+        header[..4].copy_from_slice(&124u32.to_le_bytes());
+        for mask in [0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+            header.extend(mask.to_le_bytes());
+        }
+        header.extend(b"DEBM"); // 'MBED'
+        header.extend((1u32 << 30).to_le_bytes());
+        header.extend([0; 32]);
+        header.extend((0x2_3333u32).to_le_bytes());
+        header.extend([0; 8]);
+        header.extend(4u32.to_le_bytes()); // images
+        header.extend(128u32.to_le_bytes()); // profile right after the pixel
+        header.extend(3u32.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+
+        let data = [0x30, 0x20, 0x10, 0x80, b'I', b'C', b'C'];
+        let path = std::env::temp_dir().join("v5.bmp");
+        std::fs::write(&path, bmp_file(&header, &[], &data)).unwrap();
+        let (pic, metadata) = RgbaImage::load_bmp_with_metadata(path.to_str().unwrap()).unwrap();
+        let p = &pic.pixels()[0];
+        assert_eq!((p.r, p.g, p.b, p.a), (0x10, 0x20, 0x30, 0x80));
+
+        assert_eq!(metadata.header_size, 124);
+        assert_eq!(metadata.masks.unwrap().alpha, 0xff000000);
+        let color_space = metadata.color_space.unwrap();
+        assert_eq!(
+            color_space.color_space_type,
+            ColorSpaceType::ProfileEmbedded
+        );
+        assert_eq!(color_space.endpoints[0].x, 1.0);
+        assert!((color_space.gamma[0] - 2.2).abs() < 0.001);
+        assert_eq!(color_space.intent, Some(RenderingIntent::Images));
+        assert_eq!(color_space.profile.unwrap().data, b"ICC");
+    }
+
+    #[test]
+    fn load_os2() {
+        // BITMAPCOREHEADER with an RGBTRIPLE palette
+        let mut header = vec![];
+        header.extend(12u32.to_le_bytes());
+        header.extend(3u16.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        let palette = [255, 0, 0, 0, 0, 255];
+        let data = [0b1010_0000, 0, 0, 0];
+        let pic = load_bytes(&bmp_file(&header, &palette, &data)).unwrap();
+        assert_eq!(rgb(pic.pixels()), [(255, 0, 0), (0, 0, 255), (255, 0, 0)]);
+
+        // OS/2 2.x header inside a bitmap array, offsets are from the start of the array
+        let mut header = info_header(2, 1, 24, 0, 0);
+        header[..4].copy_from_slice(&64u32.to_le_bytes());
+        header.extend([0; 24]);
+        let data = [1, 2, 3, 4, 5, 6, 0, 0];
+        let mut bitmap = bmp_file(&header, &[], &data);
+        bitmap[10..14].copy_from_slice(&(14 + 14 + 64u32).to_le_bytes());
+        let mut file = b"BA".to_vec();
+        file.extend([0; 12]);
+        file.extend(bitmap);
+        let pic = load_bytes(&file).unwrap();
+        assert_eq!(rgb(pic.pixels()), [(3, 2, 1), (6, 5, 4)]);
+
+        // monochrome icon, the XOR mask rows come before the AND mask rows
+        let palette = [0, 0, 0, 0, 255, 255, 255, 0];
+        let header = info_header(2, 4, 1, 0, 2);
+        let mut data = vec![0xee; 4];
+        data.extend([0b1000_0000, 0, 0, 0, 0b0100_0000, 0, 0, 0]);
+        data.extend([0b1100_0000, 0, 0, 0, 0b1100_0000, 0, 0, 0]);
+        for signature in [b"IC", b"PT"] {
+            let mut file = bmp_file(&header, &palette, &data);
+            file[..2].copy_from_slice(signature);
+            file[10..14].copy_from_slice(&(14 + 40 + 8 + 4u32).to_le_bytes());
+            let pic = load_bytes(&file).unwrap();
+            assert_eq!(
+                rgb(pic.pixels()),
+                [(0, 0, 0), (255, 255, 255), (255, 255, 255), (0, 0, 0)]
+            );
+            let info = BmpInfo::from_bytes(&file).unwrap();
+            assert_eq!((info.width, info.height), (2, 2));
+        }
+
+        // color icon, a mask bitmap followed by the headers of the color one
+        let file_header = |signature: &[u8], data_offset: u32| {
+            let mut header = signature.to_vec();
+            header.extend(26u32.to_le_bytes());
+            header.extend(0u32.to_le_bytes());
+            header.extend(data_offset.to_le_bytes());
+            header
+        };
+        for signature in [b"CI", b"CP"] {
+            let mut file = file_header(signature, 116);
+            file.extend(info_header(2, 4, 1, 0, 2));
+            file.extend(palette);
+            file.extend(file_header(signature, 132));
+            file.extend(info_header(2, 2, 24, 0, 0));
+            file.extend([0xff; 16]);
+            file.extend([1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0]);
+            let pic = load_bytes(&file).unwrap();
+            assert_eq!(
+                rgb(pic.pixels()),
+                [(9, 8, 7), (12, 11, 10), (3, 2, 1), (6, 5, 4)]
+            );
+            let info = BmpInfo::from_bytes(&file).unwrap();
+            assert_eq!((info.height, info.bits_per_pixel), (2, 24));
+        }
+    }
+
+    #[test]
+    fn top_down() {
+        let data = [1, 2, 3, 0, 4, 5, 6, 0];
+        let header = info_header(1, -2, 24, 0, 0);
+        let pic = load_bytes(&bmp_file(&header, &[], &data)).unwrap();
+        assert_eq!(rgb(pic.pixels()), [(3, 2, 1), (6, 5, 4)]);
+
+        let path = std::env::temp_dir().join("top_down_saved.bmp");
+        let path = path.to_str().unwrap();
+        pic.save_bmp_with_row_order(path, RowOrder::TopDown)
+            .unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(bytes[22..26], (-2i32).to_le_bytes());
+        assert_eq!(bytes[54..57], [1, 2, 3]);
+        assert_eq!(
+            rgb(RgbImage::load_bmp(path).unwrap().pixels()),
+            rgb(pic.pixels())
+        );
+    }
+
+    #[test]
+    fn data_offset() {
+        // pixel data after a gap, with a file size that doesn't match
+        let mut file = bmp_file(&info_header(1, 1, 24, 0, 0), &[0xaa; 6], &[1, 2, 3, 0]);
+        file[2..6].copy_from_slice(&100u32.to_le_bytes());
+        let path = std::env::temp_dir().join("data_offset.bmp");
+        std::fs::write(&path, &file).unwrap();
+        let (pic, metadata) = RgbImage::load_bmp_with_metadata(path.to_str().unwrap()).unwrap();
+        assert_eq!(rgb(pic.pixels()), [(3, 2, 1)]);
+        assert_eq!(
+            metadata.warnings,
+            [Warning::FileSizeMismatch {
+                declared: 100,
+                actual: file.len()
+            }]
+        );
+
+        file[10..14].copy_from_slice(&200u32.to_le_bytes());
+        let res = load_bytes(&file);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidDataOffset {
+                offset: 10,
+                field: "bfOffBits",
+                value: 200
+            })
+        ));
+    }
+
+    #[test]
+    fn warnings() {
+        let mut file = bmp_file(&info_header(1, 1, 24, 0, 0), &[], &[1, 2, 3, 0]);
+        file[6..10].copy_from_slice(&7u32.to_le_bytes());
+        file.extend([0; 5]);
+        let len = file.len() as u32;
+        file[2..6].copy_from_slice(&len.to_le_bytes());
+        let (pic, metadata) = RgbImage::read_bmp_with_metadata(file.as_slice()).unwrap();
+        assert_eq!(rgb(pic.pixels()), [(3, 2, 1)]);
+        assert_eq!(
+            metadata.warnings,
+            [Warning::NonzeroReserved(7), Warning::TrailingData(5)]
+        );
+
+        // short files fail instead of panicking
+        for len in [0, 2, 13, 14, 20, 53] {
+            assert!(matches!(
+                load_bytes(&file[..len]),
+                Err(Error::Truncated { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn save_rgba() {
+        let pixels = (0..6).map(|i| Rgba::new(i, 2 * i, 3 * i, 40 * i)).collect();
+        let pic = RgbaImage::new(pixels, 3);
+        let path = std::env::temp_dir().join("rgba_saved.bmp");
+        let path = path.to_str().unwrap();
+        pic.save_bmp(path).unwrap();
+
+        let (loaded, metadata) = RgbaImage::load_bmp_with_metadata(path).unwrap();
+        assert_eq!(metadata.header_size, 108);
+        assert_eq!(metadata.masks.unwrap().alpha, 0xff000000);
+        assert!(metadata.warnings.is_empty());
+        let pixels = |pic: &RgbaImage| {
+            pic.pixels()
+                .iter()
+                .map(|p| (p.r, p.g, p.b, p.a))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pixels(&loaded), pixels(&pic));
+    }
+
+    #[test]
+    fn encoder_options() {
+        let pixels = (0..15)
+            .map(|i| [Rgb::new(255, 0, 0), Rgb::new(0, 0, 255)][i % 3 / 2].clone())
+            .collect::<Vec<_>>();
+        let pic = RgbImage::new(pixels, 5);
+        let path = std::env::temp_dir().join("encoder_options.bmp");
+        let path = path.to_str().unwrap();
+
+        let configs = [
+            (
+                1,
+                HeaderVersion::Core,
+                RowOrder::BottomUp,
+                Compression::None,
+            ),
+            (4, HeaderVersion::Info, RowOrder::TopDown, Compression::None),
+            (8, HeaderVersion::V5, RowOrder::BottomUp, Compression::None),
+            (
+                16,
+                HeaderVersion::Info,
+                RowOrder::BottomUp,
+                Compression::None,
+            ),
+            (
+                16,
+                HeaderVersion::Info,
+                RowOrder::TopDown,
+                Compression::Bitfields,
+            ),
+            (24, HeaderVersion::V4, RowOrder::TopDown, Compression::None),
+            (
+                32,
+                HeaderVersion::Info,
+                RowOrder::BottomUp,
+                Compression::Bitfields,
+            ),
+            (
+                32,
+                HeaderVersion::V5,
+                RowOrder::BottomUp,
+                Compression::Bitfields,
+            ),
+        ];
+        for (bpp, header_version, row_order, compression) in configs {
+            let options = BmpEncoderOptions::new()
+                .bits_per_pixel(bpp)
+                .header_version(header_version)
+                .row_order(row_order)
+                .compression(compression);
+            pic.save_bmp_with(path, &options).unwrap();
+            let loaded = RgbImage::load_bmp(path).unwrap();
+            assert_eq!(rgb(loaded.pixels()), rgb(pic.pixels()), "{options:?}");
+        }
+
+        // core palettes are padded to the full table
+        let colors = [
+            Rgb::new(255, 0, 0),
+            Rgb::new(0, 255, 0),
+            Rgb::new(0, 0, 255),
+        ];
+        let three = RgbImage::new((0..8).map(|i| colors[i % 3].clone()).collect(), 4);
+        for bpp in [4, 8] {
+            let options = BmpEncoderOptions::new()
+                .bits_per_pixel(bpp)
+                .header_version(HeaderVersion::Core);
+            let mut buff = vec![];
+            three.write_bmp_with(&mut buff, &options).unwrap();
+            assert_eq!(buff.len(), 14 + 12 + 3 * (1 << bpp) + 4 * 2);
+            let loaded = RgbImage::read_bmp(&buff[..]).unwrap();
+            assert_eq!(rgb(loaded.pixels()), rgb(three.pixels()));
+        }
+
+        let gradient = RgbImage::new((0..3).map(|i| Rgb::new(i, i, i)).collect(), 3);
+        let options = BmpEncoderOptions::new()
+            .bits_per_pixel(1)
+            .quantizer(Quantizer::Exact);
+        let res = gradient.save_bmp_with(path, &options);
+        assert!(matches!(res, Err(Error::TooManyColors(2))));
+
+        let options = BmpEncoderOptions::new().compression(Compression::Bitfields);
+        let res = pic.save_bmp_with(path, &options);
+        assert!(matches!(res, Err(Error::InvalidEncoderOptions(_))));
+    }
+
+    #[test]
+    fn resolution() {
+        assert_eq!(Resolution::default(), Resolution::from_dpi(72.0, 72.0));
+        assert_eq!(Resolution::new(11811, 11811).dpi().0.round(), 300.0);
+
+        let mut pic = RgbImage::new(vec![Rgb::new(1, 2, 3)], 1);
+        assert_eq!(pic.resolution, Resolution::new(2835, 2835));
+        pic.resolution = Resolution::from_dpi(300.0, 150.0);
+
+        let path = std::env::temp_dir().join("resolution.bmp");
+        let path = path.to_str().unwrap();
+        pic.save_bmp(path).unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(bytes[46..50], [0; 4], "no colors used without a palette");
+        let loaded = RgbImage::load_bmp(path).unwrap();
+        assert_eq!(loaded.resolution, Resolution::new(11811, 5906));
+
+        let options = BmpEncoderOptions::new().resolution(Resolution::new(100, 200));
+        pic.save_bmp_with(path, &options).unwrap();
+        let loaded = RgbImage::load_bmp(path).unwrap();
+        assert_eq!(loaded.resolution, Resolution::new(100, 200));
+    }
+
+    #[test]
+    fn quantize() {
+        let pixels = (0..64 * 64)
+            .map(|i| Rgb::new((i % 64 * 4) as u8, (i / 64 * 4) as u8, 128))
+            .collect::<Vec<_>>();
+        let pic = RgbImage::new(pixels, 64);
+        let path = std::env::temp_dir().join("quantize.bmp");
+        let path = path.to_str().unwrap();
+
+        for quantizer in [Quantizer::MedianCut, Quantizer::Octree] {
+            for dither in [false, true] {
+                let options = BmpEncoderOptions::new()
+                    .bits_per_pixel(8)
+                    .quantizer(quantizer)
+                    .dither(dither);
+                pic.save_bmp_with(path, &options).unwrap();
+                let bytes = std::fs::read(path).unwrap();
+                assert_eq!(bytes[28], 8);
+                assert!(bytes.len() < 54 + 256 * 4 + 64 * 64 + 1);
+
+                let loaded = RgbImage::load_bmp(path).unwrap();
+                let error = loaded
+                    .pixels()
+                    .iter()
+                    .zip(pic.pixels().iter())
+                    .map(|(a, b)| (a.r as i32 - b.r as i32).abs() + (a.g as i32 - b.g as i32).abs())
+                    .sum::<i32>() as f32
+                    / pic.pixels().len() as f32;
+                assert!(error < 12.0, "{quantizer:?} {dither} {error}");
+            }
+        }
+
+        // octree keeps as many colors as the palette holds, even when
+        // folding whole nodes would leave fewer
+        for bits_per_pixel in [1, 4] {
+            let options = BmpEncoderOptions::new()
+                .bits_per_pixel(bits_per_pixel)
+                .quantizer(Quantizer::Octree);
+            pic.save_bmp_with(path, &options).unwrap();
+            let loaded = RgbImage::load_bmp(path).unwrap();
+            let mut colors = loaded.into_pixels();
+            colors.sort_unstable_by_key(|p| (p.r, p.g, p.b));
+            colors.dedup();
+            assert_eq!(colors.len(), 1 << bits_per_pixel, "{bits_per_pixel}");
+        }
+
+        // dithering down to two colors keeps the average brightness
+        let ramp = (0..256 * 8)
+            .map(|i| Rgb::new(i as u8, i as u8, i as u8))
+            .collect();
+        let options = BmpEncoderOptions::new().bits_per_pixel(1).dither(true);
+        RgbImage::new(ramp, 256)
+            .save_bmp_with(path, &options)
+            .unwrap();
+        let loaded = RgbImage::load_bmp(path).unwrap();
+        let middle = loaded
+            .pixels()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| (96..160).contains(&(i % 256)));
+        let mean = middle.map(|(_, p)| p.r as f32).sum::<f32>() / (64.0 * 8.0);
+        assert!((mean - 127.5).abs() < 4.0, "{mean}");
+    }
+
+    #[test]
+    fn save_rle() {
+        let colors = [Rgb::new(0, 0, 0), Rgb::new(255, 0, 0), Rgb::new(0, 255, 0)];
+        // long runs, alternating pairs and short noise, odd width
+        let pixels = (0..37 * 9)
+            .map(|i: usize| match i % 37 {
+                0..=19 => colors[i / 37 % 3].clone(),
+                20..=27 => colors[i % 2 + 1].clone(),
+                _ => colors[(i * 7 + i / 3) % 3].clone(),
+            })
+            .collect::<Vec<_>>();
+        let pic = RgbImage::new(pixels, 37);
+        let path = std::env::temp_dir().join("rle_saved.bmp");
+        let path = path.to_str().unwrap();
+
+        for (bpp, compression) in [(8, 1), (4, 2)] {
+            let options = BmpEncoderOptions::new()
+                .bits_per_pixel(bpp)
+                .compression(Compression::Rle);
+            pic.save_bmp_with(path, &options).unwrap();
+            let bytes = std::fs::read(path).unwrap();
+            assert_eq!(bytes[30], compression);
+            let image_size = u32::from_le_bytes(bytes[34..38].try_into().unwrap()) as usize;
+            assert_eq!(&bytes[bytes.len() - 2..], [0, 1]);
+            assert!(image_size < 37 * 9 * bpp as usize / 8);
+
+            let loaded = RgbImage::load_bmp(path).unwrap();
+            assert_eq!(rgb(loaded.pixels()), rgb(pic.pixels()));
+        }
+
+        let options = BmpEncoderOptions::new().compression(Compression::Rle);
+        let res = pic.save_bmp_with(path, &options);
+        assert!(matches!(res, Err(Error::InvalidEncoderOptions(_))));
+    }
+
+    #[test]
+    fn read_write() {
+        let pic = RgbaImage::new(vec![Rgba::new(1, 2, 3, 4), Rgba::new(5, 6, 7, 8)], 1);
+        let mut buff = vec![];
+        pic.write_bmp(&mut buff).unwrap();
+        let loaded = RgbaImage::read_bmp(buff.as_slice()).unwrap();
+        let pixels = loaded
+            .pixels()
+            .iter()
+            .map(|p| (p.r, p.g, p.b, p.a))
+            .collect::<Vec<_>>();
+        assert_eq!(pixels, [(1, 2, 3, 4), (5, 6, 7, 8)]);
+
+        let path = std::env::temp_dir().join("read_write.bmp");
+        pic.save_bmp(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), buff);
+
+        let mut cursor = std::io::Cursor::new(vec![]);
+        let options = BmpEncoderOptions::new().bits_per_pixel(8);
+        RgbImage::load_bmp(&path)
+            .unwrap()
+            .write_bmp_with(&mut cursor, &options)
+            .unwrap();
+        cursor.set_position(0);
+        let loaded = RgbImage::read_bmp(cursor).unwrap();
+        assert_eq!(rgb(loaded.pixels()), [(1, 2, 3), (5, 6, 7)]);
+    }
+
+    #[test]
+    fn row_writer() {
+        let pixels = (0..5 * 3)
+            .map(|i| Rgb::new(i, 2 * i, 3 * i))
+            .collect::<Vec<_>>();
+        let pic = RgbImage::new(pixels.clone(), 5);
+
+        for bpp in [16, 24, 32] {
+            for file_order in [RowOrder::BottomUp, RowOrder::TopDown] {
+                let options = BmpEncoderOptions::new()
+                    .bits_per_pixel(bpp)
+                    .row_order(file_order);
+                let mut expected = vec![];
+                pic.write_bmp_with(&mut expected, &options).unwrap();
+
+                for input_order in [RowOrder::BottomUp, RowOrder::TopDown] {
+                    let cursor = std::io::Cursor::new(vec![]);
+                    let mut writer =
+                        BmpRowWriter::new(cursor, 5, 3, input_order, &options).unwrap();
+                    let mut rows = pixels.chunks(5).collect::<Vec<_>>();
+                    if input_order == RowOrder::BottomUp {
+                        rows.reverse();
+                    }
+                    for row in rows {
+                        writer.write_row(row).unwrap();
+                    }
+                    let written = writer.finish().unwrap();
+                    assert_eq!(written.position() as usize, expected.len());
+                    assert_eq!(written.into_inner(), expected);
+                }
+            }
+        }
+
+        let cursor = std::io::Cursor::new(vec![]);
+        let options = BmpEncoderOptions::new();
+        let mut writer = BmpRowWriter::new(cursor, 5, 3, RowOrder::TopDown, &options).unwrap();
+        assert!(matches!(
+            writer.write_row(&pixels[..4]),
+            Err(Error::InvalidRowLength {
+                expected: 5,
+                got: 4
+            })
+        ));
+        writer.write_row(&pixels[..5]).unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(Error::RowCountMismatch {
+                expected: 3,
+                got: 1
+            })
+        ));
+
+        let cursor = std::io::Cursor::new(vec![]);
+        let options = BmpEncoderOptions::new().bits_per_pixel(8);
+        let res = BmpRowWriter::new(cursor, 5, 3, RowOrder::TopDown, &options);
+        assert!(matches!(res, Err(Error::InvalidEncoderOptions(_))));
+
+        // only the headers are written, sizes past 32 bits are rejected
+        let options = BmpEncoderOptions::new();
+        let mut header = vec![];
+        let mut cursor = std::io::Cursor::new(&mut header);
+        BmpRowWriter::new(&mut cursor, 60000, 20000, RowOrder::TopDown, &options).unwrap();
+        assert_eq!(header[2..6], (54 + 180000 * 20000u32).to_le_bytes());
+        assert_eq!(header[34..38], (180000 * 20000u32).to_le_bytes());
+        for (width, height) in [(70000, 70000), (1 << 31, 1), (1, 1 << 31)] {
+            let cursor = std::io::Cursor::new(vec![]);
+            let res = BmpRowWriter::new(cursor, width, height, RowOrder::TopDown, &options);
+            assert!(
+                matches!(res, Err(Error::ImageTooLarge { .. })),
+                "{width}x{height}"
+            );
+        }
+    }
+
+    #[test]
+    fn decoder() {
+        let pixels = (0..7 * 5)
+            .map(|i| Rgb::new((i % 4) * 60, (i % 3) * 100, 0))
+            .collect::<Vec<_>>();
+        let pic = RgbImage::new(pixels.clone(), 7);
+        let region = |x: usize, y: usize, w: usize, h: usize| {
+            (y..y + h)
+                .flat_map(|row| pixels[row * 7 + x..row * 7 + x + w].to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        for bpp in [4, 8, 16, 24, 32] {
+            for order in [RowOrder::BottomUp, RowOrder::TopDown] {
+                let options = BmpEncoderOptions::new()
+                    .bits_per_pixel(bpp)
+                    .row_order(order);
+                let mut buff = vec![];
+                pic.write_bmp_with(&mut buff, &options).unwrap();
+                if bpp == 16 {
+                    // 5 bit channels lose the low bits
+                    continue;
+                }
+
+                let mut decoder = BmpDecoder::new(std::io::Cursor::new(buff)).unwrap();
+                assert_eq!((decoder.width(), decoder.height()), (7, 5));
+                assert_eq!(decoder.bits_per_pixel(), bpp);
+                assert_eq!(decoder.compression(), Compression::None);
+
+                let row = decoder.read_row(3).unwrap();
+                assert_eq!(rgb(&to_rgb(&row)), rgb(&region(0, 3, 7, 1)));
+                let tile = decoder.read_region(1, 2, 3, 2).unwrap();
+                assert_eq!(rgb(&to_rgb(&tile)), rgb(&region(1, 2, 3, 2)));
+
+                let rows = decoder.rows().collect::<Result<Vec<_>, _>>().unwrap();
+                assert_eq!(rgb(&to_rgb(&rows.concat())), rgb(&pixels));
+            }
+        }
+
+        let options = BmpEncoderOptions::new()
+            .bits_per_pixel(8)
+            .compression(Compression::Rle);
+        let mut buff = vec![];
+        pic.write_bmp_with(&mut buff, &options).unwrap();
+        let mut decoder = BmpDecoder::new(std::io::Cursor::new(buff)).unwrap();
+        assert_eq!(decoder.compression(), Compression::Rle);
+        let tile = decoder.read_region(4, 1, 3, 4).unwrap();
+        assert_eq!(rgb(&to_rgb(&tile)), rgb(&region(4, 1, 3, 4)));
+
+        assert!(matches!(
+            decoder.read_region(5, 0, 3, 1),
+            Err(Error::RegionOutOfBounds { .. })
+        ));
+        assert!(matches!(
+            decoder.read_row(5),
+            Err(Error::RegionOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn bmp_info() {
+        let pixels = (0..6 * 4).map(|i| Rgb::new(i * 10, 0, 0)).collect();
+        let mut pic = RgbImage::new(pixels, 6);
+        pic.resolution = Resolution::new(3780, 3780);
+        let options = BmpEncoderOptions::new()
+            .bits_per_pixel(8)
+            .header_version(HeaderVersion::V5)
+            .row_order(RowOrder::TopDown);
+        let mut buff = vec![];
+        pic.write_bmp_with(&mut buff, &options).unwrap();
+
+        let expected = BmpInfo {
+            width: 6,
+            height: 4,
+            top_down: true,
+            bits_per_pixel: 8,
+            compression: Compression::None,
+            header_size: 124,
+            header_version: Some(HeaderVersion::V5),
+            palette_size: 24,
+            resolution: Resolution::new(3780, 3780),
+        };
+        // the pixels aren't needed
+        assert_eq!(BmpInfo::from_bytes(&buff[..14 + 124]).unwrap(), expected);
+        assert_eq!(BmpInfo::read(buff.as_slice()).unwrap(), expected);
+
+        let path = std::env::temp_dir().join("save_as_bmp_info.bmp");
+        pic.save_bmp_with(&path, &options).unwrap();
+        assert_eq!(BmpInfo::load(&path).unwrap(), expected);
+        std::fs::remove_file(&path).unwrap();
+
+        let header = info_header(3, 2, 4, 2, 0);
+        let info = BmpInfo::from_bytes(&bmp_file(&header, &[], &[0, 1])).unwrap();
+        assert_eq!(info.compression, Compression::Rle);
+        assert_eq!(info.palette_size, 16);
+        assert!(!info.top_down);
+        assert_eq!(info.header_version, Some(HeaderVersion::Info));
+
+        assert!(matches!(
+            BmpInfo::from_bytes(&buff[..20]),
+            Err(Error::Truncated {
+                offset: 14,
+                needed: 124
+            })
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        // 54 bytes asking for a 2^31 x 2^16 image
+        let file = bmp_file(&info_header(i32::MAX, 1 << 16, 24, 0, 0), &[], &[]);
+        assert!(matches!(
+            load_bytes(&file),
+            Err(Error::LimitExceeded("width"))
+        ));
+        let options = BmpDecoderOptions::new().limits(Limits::none());
+        assert!(matches!(
+            RgbImage::read_bmp_with(file.as_slice(), &options),
+            Err(Error::Truncated { offset: 54, .. })
+        ));
+
+        let file = bmp_file(&info_header(4, 3, 8, 1, 0), &[0; 1024], &[0, 1]);
+        let limits = Limits {
+            max_pixels: 11,
+            ..Limits::default()
+        };
+        let options = BmpDecoderOptions::new().limits(limits);
+        assert!(matches!(
+            RgbImage::read_bmp_with(file.as_slice(), &options),
+            Err(Error::LimitExceeded("total pixels"))
+        ));
+        // the lazy decoder only checks what it allocates, RLE decodes the
+        // whole image on first access
+        let mut decoder = BmpDecoder::with_options(std::io::Cursor::new(&file), &options).unwrap();
+        let res = decoder.read_row(0);
+        assert!(matches!(res, Err(Error::LimitExceeded("total pixels"))));
+
+        let file = bmp_file(&info_header(4, 3, 24, 0, 0), &[], &[0; 48]);
+        let options = BmpDecoderOptions::new().limits(Limits {
+            max_pixels: 5,
+            ..Limits::default()
+        });
+        let mut decoder = BmpDecoder::with_options(std::io::Cursor::new(&file), &options).unwrap();
+        assert_eq!(decoder.read_row(2).unwrap().len(), 4);
+        let res = decoder.read_region(0, 0, 4, 2);
+        assert!(matches!(res, Err(Error::LimitExceeded("total pixels"))));
+
+        // decoded Rgba plus the image's own pixels
+        let limits = Limits {
+            max_alloc: (4 + 3) * 12,
+            ..limits
+        };
+        let options = BmpDecoderOptions::new().limits(Limits {
+            max_pixels: 12,
+            ..limits
+        });
+        let (pic, _) = RgbImage::read_bmp_with(file.as_slice(), &options).unwrap();
+        assert_eq!(pic.pixels().len(), 12);
+        let res = Image::<Rgba<f32>>::read_bmp_with(file.as_slice(), &options);
+        assert!(matches!(res, Err(Error::LimitExceeded("allocation"))));
+    }
+
+    #[test]
+    fn errors() {
+        let file = bmp_file(&info_header(2, 2, 7, 0, 0), &[], &[0; 8]);
+        let err = load_bytes(&file).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::UnsupportedColorDepth {
+                offset: 28,
+                field: "biBitCount",
+                value: 7
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Unsupported color depth of 7 bpp in biBitCount at offset 28"
+        );
+
+        let file = bmp_file(&info_header(2, 2, 4, 0, 17), &[0; 64], &[0; 8]);
+        assert!(matches!(
+            load_bytes(&file),
+            Err(Error::InvalidPaletteSize {
+                offset: 46,
+                field: "biClrUsed",
+                value: 17
+            })
+        ));
+
+        let mut file = bmp_file(&info_header(2, 2, 24, 0, 0), &[], &[0; 15]);
+        file[0] = b'X';
+        assert!(matches!(
+            load_bytes(&file),
+            Err(Error::InvalidSignature { offset: 0, .. })
+        ));
+        file[0] = b'B';
+        assert!(matches!(
+            load_bytes(&file),
+            Err(Error::Truncated {
+                offset: 54,
+                needed: 16
+            })
+        ));
+
+        // composes with boxed errors and keeps the I/O cause
+        let res: Result<RgbImage, Box<dyn std::error::Error>> =
+            RgbImage::load_bmp("does/not/exist.bmp").map_err(Into::into);
+        let err = res.unwrap_err();
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn recover() {
+        let pixels = (0..3 * 4).map(|i| Rgb::new(i, i, i)).collect::<Vec<_>>();
+        let pic = RgbImage::new(pixels.clone(), 3);
+        let mut buff = vec![];
+        pic.write_bmp(&mut buff).unwrap();
+        // two full rows of 12 bytes and 2 pixels of the third
+        let cut = &buff[..54 + 2 * 12 + 7];
+        assert!(matches!(load_bytes(cut), Err(Error::Truncated { .. })));
+
+        let red = Rgba::new(255, 0, 0, 255);
+        let options = BmpDecoderOptions::new()
+            .recover(true)
+            .fill_color(red.clone());
+        let (recovered, metadata) = RgbImage::read_bmp_with(cut, &options).unwrap();
+        // bottom-up, so the top row is missing and the one below it is partial
+        let mut expected = pixels.clone();
+        for i in [0, 1, 2, 5] {
+            expected[i] = Rgb::from(red.clone());
+        }
+        assert_eq!(rgb(recovered.pixels()), rgb(&expected));
+        assert!(metadata.warnings.contains(&Warning::MissingRows(2)));
+
+        let mut decoder = BmpDecoder::with_options(std::io::Cursor::new(cut), &options).unwrap();
+        let rows = decoder.rows().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(rgb(&to_rgb(&rows.concat())), rgb(&expected));
+
+        // bogus header and image sizes
+        let mut file = bmp_file(&info_header(1, 1, 24, 0, 0), &[], &[1, 2, 3, 0]);
+        file[34..38].copy_from_slice(&1000u32.to_le_bytes());
+        let (_, metadata) = RgbImage::read_bmp_with_metadata(file.as_slice()).unwrap();
+        assert_eq!(
+            metadata.warnings,
+            [Warning::ImageSizeMismatch {
+                declared: 1000,
+                actual: 4
+            }]
+        );
+        file[14..18].copy_from_slice(&41u32.to_le_bytes());
+        assert!(matches!(
+            load_bytes(&file),
+            Err(Error::InvalidHeaderSize { value: 41, .. })
+        ));
+        let (repaired, metadata) = RgbImage::read_bmp_with(file.as_slice(), &options).unwrap();
+        assert_eq!(rgb(repaired.pixels()), [(3, 2, 1)]);
+        assert!(metadata.warnings.contains(&Warning::HeaderSizeRepaired {
+            declared: 41,
+            used: 40
+        }));
+
+        // V5 header with the profile after the pixels, cut in the second row
+        let mut header = info_header(2, 2, 24, 0, 0);
+        header[..4].copy_from_slice(&124u32.to_le_bytes());
+        header.extend([0; 16]);
+        header.extend(b"DEBM"); // 'MBED'
+        header.extend([0; 48]);
+        header.extend(4u32.to_le_bytes()); // images
+        header.extend(140u32.to_le_bytes());
+        header.extend(4u32.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        let data = [
+            1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0, b'I', b'C', b'C', 0,
+        ];
+        let file = bmp_file(&header, &[], &data);
+        let cut = &file[..14 + 124 + 10];
+        assert!(matches!(
+            load_bytes(cut),
+            Err(Error::Truncated {
+                offset: 154,
+                needed: 4
+            })
+        ));
+        let (recovered, metadata) = RgbImage::read_bmp_with(cut, &options).unwrap();
+        assert_eq!(
+            rgb(recovered.pixels()),
+            [(255, 0, 0), (255, 0, 0), (3, 2, 1), (6, 5, 4)]
+        );
+        assert!(metadata.warnings.contains(&Warning::TruncatedProfile(4)));
+        assert!(metadata.color_space.unwrap().profile.is_none());
+        let decoder = BmpDecoder::with_options(std::io::Cursor::new(cut), &options).unwrap();
+        assert!(decoder
+            .metadata()
+            .color_space
+            .as_ref()
+            .unwrap()
+            .profile
+            .is_none());
+        assert!(decoder
+            .metadata()
+            .warnings
+            .contains(&Warning::TruncatedProfile(4)));
+
+        // RLE data cut short in the second row
+        let gray = (0..4).flat_map(|i| [i, i, i, 0]).collect::<Vec<_>>();
+        let header = info_header(2, 3, 8, 1, 4);
+        let file = bmp_file(&header, &gray, &[2, 1, 0, 0, 1, 2]);
+        assert!(matches!(load_bytes(&file), Err(Error::Truncated { .. })));
+        let (recovered, metadata) = RgbImage::read_bmp_with(file.as_slice(), &options).unwrap();
+        assert_eq!(
+            rgb(recovered.pixels()),
+            [
+                (255, 0, 0),
+                (255, 0, 0),
+                (2, 2, 2),
+                (255, 0, 0),
+                (1, 1, 1),
+                (1, 1, 1)
+            ]
+        );
+        assert_eq!(metadata.warnings, [Warning::MissingRows(2)]);
+    }
+
+    #[test]
+    fn dimensions() {
+        let res = RgbImage::try_new(vec![Rgb::default(); 7], 3);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidDimensions { width: 3, len: 7 })
+        ));
+        let res = RgbImage::try_new(vec![Rgb::default(); 2], 0);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidDimensions { width: 0, len: 2 })
+        ));
+
+        let pic = RgbImage::from_fn(3, 2, |x, y| Rgb::new(x as u8, y as u8, 0));
+        assert_eq!((pic.width(), pic.height()), (3, 2));
+        assert_eq!(pic.pixels()[4], Rgb::new(1, 1, 0));
+
+        let mut pic = RgbaImage::filled(2, 4, Rgba::new(1, 2, 3, 4));
+        assert_eq!(pic.height(), 4);
+        pic.pixels_mut()[7] = Rgba::default();
+        assert_eq!(pic.into_pixels()[6], Rgba::new(1, 2, 3, 4));
+
+        // empty images can't be encoded
+        let pic = RgbImage::try_new(vec![], 0).unwrap();
+        assert_eq!(pic.height(), 0);
+        let res = pic.write_bmp(vec![]);
+        assert!(matches!(res, Err(Error::InvalidDimensions { .. })));
+        let pic = RgbImage::try_new(vec![], 3).unwrap();
+        let options = BmpEncoderOptions::new()
+            .bits_per_pixel(8)
+            .compression(Compression::Rle);
+        for options in [BmpEncoderOptions::new(), options] {
+            let res = pic.write_bmp_with(vec![], &options);
+            assert!(matches!(
+                res,
+                Err(Error::InvalidDimensions { width: 3, len: 0 })
+            ));
+        }
+    }
+
+    #[test]
+    fn pixel_types() {
+        let mut buff = vec![];
+        let pic = GrayImage::from_fn(4, 2, |x, y| Luma((x * 60 + y) as u8));
+        pic.write_bmp(&mut buff).unwrap();
+        let (loaded, metadata) = GrayImage::read_bmp_with_metadata(&buff[..]).unwrap();
+        assert_eq!(BmpInfo::from_bytes(&buff).unwrap().bits_per_pixel, 8);
+        assert!(metadata.warnings.is_empty());
+        assert_eq!(loaded.pixels(), pic.pixels());
+
+        // colors are loaded as their luminance
+        let colors = RgbImage::new(vec![Rgb::new(255, 0, 0), Rgb::new(128, 128, 128)], 2);
+        buff.clear();
+        colors.write_bmp(&mut buff).unwrap();
+        let gray = GrayImage::read_bmp(&buff[..]).unwrap();
+        assert_eq!(gray.pixels(), [Luma(127), Luma(128)]);
+        let gray = Gray16Image::read_bmp(&buff[..]).unwrap();
+        assert_eq!(gray.pixels()[1], Luma(128 * 257));
+
+        let deep = Rgb16Image::from_fn(3, 1, |x, _| Rgb::new(x as u16 * 30000, 65535, 257));
+        buff.clear();
+        deep.write_bmp(&mut buff).unwrap();
+        let loaded = RgbImage::read_bmp(&buff[..]).unwrap();
+        assert_eq!(loaded.pixels()[2], Rgb::new(233, 255, 1));
+
+        // f32 is linear light, mid gray is about 0.2 of full intensity
+        let linear: Rgb32FImage = colors.convert();
+        assert_eq!(linear.pixels()[0], Rgb::new(1.0, 0.0, 0.0));
+        assert!((linear.pixels()[1].g - 0.2158).abs() < 1e-3);
+        assert_eq!(linear.convert::<Rgb>().pixels(), colors.pixels());
+        let rgba: RgbaImage = colors.convert();
+        assert_eq!(rgba.pixels()[1], Rgba::new(128, 128, 128, 255));
+        assert_eq!(Luma::<u16>::CHANNELS + Rgba::<f32>::CHANNELS, 5);
+        assert_eq!(
+            Rgb::new(0.5f32, 0.5, 0.5).to_rgba8(),
+            Rgba::new(188, 188, 188, 255)
+        );
+    }
+
+    #[test]
+    fn views() {
+        let mut pic = RgbImage::from_fn(6, 4, |x, y| Rgb::new(x as u8, y as u8, 0));
+        let view = pic.view(1, 1, 4, 2).unwrap();
+        assert_eq!((view.width(), view.height()), (4, 2));
+        assert_eq!(view.get_pixel(3, 1), &Rgb::new(4, 2, 0));
+        let rows = view.rows().map(rgb).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][0], (1, 2, 0));
+
+        // views of views are relative to their parent
+        let inner = view.view(2, 1, 2, 1).unwrap();
+        assert_eq!(rgb(inner.row(0)), [(3, 2, 0), (4, 2, 0)]);
+        let res = view.view(3, 0, 2, 1);
+        assert!(matches!(res, Err(Error::RegionOutOfBounds { x: 3, .. })));
+        assert!(pic.view(0, 3, 6, 2).is_err());
+
+        // only the rectangle is encoded
+        let mut buff = vec![];
+        view.write_bmp(&mut buff).unwrap();
+        let loaded = RgbImage::read_bmp(&buff[..]).unwrap();
+        assert_eq!(loaded.pixels(), view.to_image().pixels());
+        assert_eq!(loaded.pixels()[4], Rgb::new(1, 2, 0));
+        buff.clear();
+        pic.view(0, 0, 3, 2)
+            .unwrap()
+            .write_bmp_with(&mut buff, &BmpEncoderOptions::new().bits_per_pixel(4))
+            .unwrap();
+        let loaded = RgbImage::read_bmp(&buff[..]).unwrap();
+        assert_eq!(
+            loaded.pixels(),
+            pic.view(0, 0, 3, 2).unwrap().to_image().pixels()
+        );
+
+        let mut view = pic.view_mut(2, 0, 3, 3).unwrap();
+        view.put_pixel(0, 0, Rgb::new(9, 9, 9));
+        view.view_mut(1, 1, 2, 2).unwrap().fill(Rgb::new(7, 7, 7));
+        for row in view.rows_mut() {
+            row[2].b = 1;
+        }
+        assert_eq!(view.get_pixel(1, 2), &Rgb::new(7, 7, 7));
+        assert_eq!(pic.pixels()[2], Rgb::new(9, 9, 9));
+        assert_eq!(pic.pixels()[6 + 4], Rgb::new(7, 7, 1));
+        assert_eq!(pic.pixels()[6 + 5], Rgb::new(5, 1, 0));
+        assert_eq!(pic.pixels()[18 + 3], Rgb::new(3, 3, 0));
+
+        let empty: ImageView<Rgb> = pic.view(6, 4, 0, 0).unwrap();
+        assert_eq!(empty.rows().count(), 0);
+        assert!(empty.to_image().pixels().is_empty());
+    }
+
+    #[test]
+    fn transforms() {
+        // 0 1 2
+        // 3 4 5
+        let pic = RgbImage::from_fn(3, 2, |x, y| Rgb::new((y * 3 + x) as u8, 0, 0));
+        let order = |pic: &RgbImage| {
+            let order = pic.pixels().iter().map(|p| p.r).collect::<Vec<_>>();
+            (pic.width(), pic.height(), order)
+        };
+
+        type Transform = (fn(&RgbImage) -> RgbImage, fn(&mut RgbImage));
+        let cases: [(Transform, (u32, Vec<u8>)); 6] = [
+            (
+                (
+                    RgbImage::flip_horizontal,
+                    RgbImage::flip_horizontal_in_place,
+                ),
+                (3, vec![2, 1, 0, 5, 4, 3]),
+            ),
+            (
+                (RgbImage::flip_vertical, RgbImage::flip_vertical_in_place),
+                (3, vec![3, 4, 5, 0, 1, 2]),
+            ),
+            (
+                (RgbImage::rotate90, RgbImage::rotate90_in_place),
+                (2, vec![3, 0, 4, 1, 5, 2]),
+            ),
+            (
+                (RgbImage::rotate180, RgbImage::rotate180_in_place),
+                (3, vec![5, 4, 3, 2, 1, 0]),
+            ),
+            (
+                (RgbImage::rotate270, RgbImage::rotate270_in_place),
+                (2, vec![2, 5, 1, 4, 0, 3]),
+            ),
+            (
+                (RgbImage::transpose, RgbImage::transpose_in_place),
+                (2, vec![0, 3, 1, 4, 2, 5]),
+            ),
+        ];
+        for ((copy, in_place), (width, expected)) in cases {
+            let copied = copy(&pic);
+            let mut changed = pic.clone();
+            in_place(&mut changed);
+            assert_eq!(order(&copied), (width, 6 / width, expected));
+            assert_eq!(order(&changed), order(&copied));
+        }
+
+        // square in place transposes cross tile boundaries
+        let mut square = RgbImage::from_fn(70, 70, |x, y| Rgb::new(x as u8, y as u8, 0));
+        square.resolution = Resolution::new(100, 200);
+        let rotated = square.rotate90();
+        assert_eq!(rotated.resolution, Resolution::new(200, 100));
+        assert_eq!(rotated.pixels()[65 * 70 + 69], Rgb::new(65, 0, 0));
+        let mut rotated_in_place = square.clone();
+        rotated_in_place.rotate90_in_place();
+        assert_eq!(rotated_in_place.pixels(), rotated.pixels());
+        assert_eq!(rotated_in_place.resolution, rotated.resolution);
+        rotated_in_place.rotate270_in_place();
+        rotated_in_place.rotate270_in_place();
+        assert_eq!(rotated_in_place.pixels(), square.rotate270().pixels());
+        square.transpose_in_place();
+        assert_eq!(square.resolution, Resolution::new(200, 100));
+        assert_eq!(square.pixels()[3 * 70 + 67], Rgb::new(3, 67, 0));
+        assert_eq!(square.pixels()[68 * 70 + 1], Rgb::new(68, 1, 0));
+
+        let pic = RgbImage::from_fn(5, 4, |x, y| Rgb::new(x as u8, y as u8, 0));
+        let cropped = pic.crop(1, 2, 3, 2).unwrap();
+        let mut in_place = pic.clone();
+        in_place.crop_in_place(1, 2, 3, 2).unwrap();
+        assert_eq!((cropped.width(), cropped.height()), (3, 2));
+        assert_eq!(cropped.pixels()[5], Rgb::new(3, 3, 0));
+        assert_eq!(in_place.pixels(), cropped.pixels());
+        let res = in_place.crop_in_place(1, 0, 3, 1);
+        assert!(matches!(res, Err(Error::RegionOutOfBounds { .. })));
+        assert_eq!(in_place.width(), 3);
+    }
+
+    #[test]
+    fn resize() {
+        let filters = [
+            Filter::Nearest,
+            Filter::Bilinear,
+            Filter::Bicubic,
+            Filter::Lanczos3,
+        ];
+        let flat = RgbImage::filled(7, 5, Rgb::new(10, 100, 200));
+        for filter in filters {
+            for (width, height) in [(3, 2), (16, 9), (7, 1)] {
+                let resized = flat.resize(width, height, filter);
+                assert_eq!((resized.width(), resized.height()), (width, height));
+                assert!(resized.pixels().iter().all(|p| p == &flat.pixels()[0]));
+            }
+        }
+
+        let pic = RgbImage::new(vec![Rgb::new(0, 0, 0), Rgb::new(255, 255, 255)], 2);
+        let nearest = pic.resize(4, 2, Filter::Nearest);
+        assert_eq!(nearest.pixels()[1], Rgb::new(0, 0, 0));
+        assert_eq!(nearest.pixels()[6], Rgb::new(255, 255, 255));
+
+        // averaging black and white gives mid gray in sRGB, but half the
+        // light in linear light is a lot brighter
+        let gray = pic.resize(1, 1, Filter::Bilinear);
+        assert!((127..=128).contains(&gray.pixels()[0].g));
+        let options = ResizeOptions::new()
+            .filter(Filter::Bilinear)
+            .linear_light(true);
+        let gray = pic.resize_with(1, 1, &options);
+        assert_eq!(gray.pixels(), [Rgb::new(188, 188, 188)]);
+
+        // transparent pixels don't bleed their color
+        let pic = RgbaImage::new(vec![Rgba::new(255, 0, 0, 255), Rgba::new(0, 0, 255, 0)], 2);
+        let blended = pic.resize(1, 1, Filter::Bicubic);
+        assert_eq!(blended.pixels(), [Rgba::new(255, 0, 0, 128)]);
+
+        let pic = RgbImage::from_fn(300, 200, |x, y| Rgb::new(x as u8, y as u8, 0));
+        let thumbnail = pic.thumbnail(64, 64);
+        assert_eq!((thumbnail.width(), thumbnail.height()), (64, 43));
+        let thumbnail = pic.thumbnail(1000, 1000);
+        assert_eq!(thumbnail.pixels(), pic.pixels());
+        assert!(RgbImage::new(vec![], 0)
+            .resize(4, 4, Filter::Bicubic)
+            .pixels()
+            .is_empty());
+
+        // float pixels keep values above 1
+        let hdr = Rgb32FImage::filled(4, 4, Rgb::new(4.0, 0.5, 0.0));
+        for filter in filters {
+            let resized = hdr.resize(3, 5, filter);
+            let p = &resized.pixels()[7];
+            assert!(
+                (p.r - 4.0).abs() < 1e-4 && (p.g - 0.5).abs() < 1e-4,
+                "{filter:?} {p:?}"
+            );
+        }
+    }
 }
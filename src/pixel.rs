@@ -0,0 +1,286 @@
+use std::fmt::Debug;
+
+use crate::{BmpEncoderOptions, Compression, HeaderVersion};
+
+// A color channel. Integer channels hold sRGB encoded values, float
+// channels hold linear light.
+pub trait Channel: Copy + Default + Debug + PartialEq + 'static {
+    const LINEAR: bool = false;
+
+    // Color value as 8 bit sRGB
+    fn to_u8(self) -> u8;
+    fn from_u8(value: u8) -> Self;
+
+    // Color value as linear light in 0..=1
+    fn to_linear(self) -> f32;
+    fn from_linear(value: f32) -> Self;
+
+    // Scaled to 0..=1 without any transfer function, used for alpha
+    fn to_unit(self) -> f32;
+    fn from_unit(value: f32) -> Self;
+}
+
+impl Channel for u8 {
+    fn to_u8(self) -> u8 {
+        self
+    }
+
+    fn from_u8(value: u8) -> Self {
+        value
+    }
+
+    fn to_linear(self) -> f32 {
+        srgb_to_linear(self.to_unit())
+    }
+
+    fn from_linear(value: f32) -> Self {
+        Self::from_unit(linear_to_srgb(value))
+    }
+
+    fn to_unit(self) -> f32 {
+        self as f32 / 255.0
+    }
+
+    fn from_unit(value: f32) -> Self {
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+}
+
+impl Channel for u16 {
+    fn to_u8(self) -> u8 {
+        ((self as u32 * 255 + 32767) / 65535) as u8
+    }
+
+    fn from_u8(value: u8) -> Self {
+        value as u16 * 257
+    }
+
+    fn to_linear(self) -> f32 {
+        srgb_to_linear(self.to_unit())
+    }
+
+    fn from_linear(value: f32) -> Self {
+        Self::from_unit(linear_to_srgb(value))
+    }
+
+    fn to_unit(self) -> f32 {
+        self as f32 / 65535.0
+    }
+
+    fn from_unit(value: f32) -> Self {
+        (value.clamp(0.0, 1.0) * 65535.0).round() as u16
+    }
+}
+
+// Linear light, values above 1 are clipped when converted to integers
+impl Channel for f32 {
+    const LINEAR: bool = true;
+
+    fn to_u8(self) -> u8 {
+        u8::from_linear(self)
+    }
+
+    fn from_u8(value: u8) -> Self {
+        value.to_linear()
+    }
+
+    fn to_linear(self) -> f32 {
+        self
+    }
+
+    fn from_linear(value: f32) -> Self {
+        value
+    }
+
+    fn to_unit(self) -> f32 {
+        self
+    }
+
+    fn from_unit(value: f32) -> Self {
+        value
+    }
+}
+
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// What an image is made of. Bitmaps store 8 bit sRGB, so pixels go through
+// Rgba when they're saved and loaded, and through linear light RGBA when
+// converted to other pixel types.
+pub trait Pixel: Clone + Debug + PartialEq {
+    const CHANNELS: usize;
+    // Whether the channels hold linear light, which may go above 1
+    const LINEAR: bool;
+
+    fn to_rgba8(&self) -> Rgba;
+    fn from_rgba8(rgba: &Rgba) -> Self;
+
+    // Linear light color with alpha, all in 0..=1
+    fn to_linear_rgba(&self) -> [f32; 4];
+    fn from_linear_rgba(rgba: [f32; 4]) -> Self;
+
+    fn convert<Q: Pixel>(&self) -> Q {
+        Q::from_linear_rgba(self.to_linear_rgba())
+    }
+
+    // What save_bmp and write_bmp write
+    fn encoder_options() -> BmpEncoderOptions {
+        BmpEncoderOptions::new()
+    }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rgb<T = u8> {
+    pub r: T,
+    pub g: T,
+    pub b: T,
+}
+
+impl<T> Rgb<T> {
+    pub fn new(r: T, g: T, b: T) -> Self {
+        Self { r, g, b }
+    }
+}
+
+impl<T> From<Rgba<T>> for Rgb<T> {
+    fn from(p: Rgba<T>) -> Self {
+        Self::new(p.r, p.g, p.b)
+    }
+}
+
+impl<T: Channel> Pixel for Rgb<T> {
+    const CHANNELS: usize = 3;
+    const LINEAR: bool = T::LINEAR;
+
+    fn to_rgba8(&self) -> Rgba {
+        Rgba::new(self.r.to_u8(), self.g.to_u8(), self.b.to_u8(), 255)
+    }
+
+    fn from_rgba8(rgba: &Rgba) -> Self {
+        Self::new(T::from_u8(rgba.r), T::from_u8(rgba.g), T::from_u8(rgba.b))
+    }
+
+    fn to_linear_rgba(&self) -> [f32; 4] {
+        [
+            self.r.to_linear(),
+            self.g.to_linear(),
+            self.b.to_linear(),
+            1.0,
+        ]
+    }
+
+    fn from_linear_rgba([r, g, b, _]: [f32; 4]) -> Self {
+        Self::new(T::from_linear(r), T::from_linear(g), T::from_linear(b))
+    }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rgba<T = u8> {
+    pub r: T,
+    pub g: T,
+    pub b: T,
+    pub a: T,
+}
+
+impl<T> Rgba<T> {
+    pub fn new(r: T, g: T, b: T, a: T) -> Self {
+        Self { r, g, b, a }
+    }
+}
+
+// Opaque
+impl<T: Channel> From<Rgb<T>> for Rgba<T> {
+    fn from(p: Rgb<T>) -> Self {
+        Self::new(p.r, p.g, p.b, T::from_unit(1.0))
+    }
+}
+
+impl<T: Channel> Pixel for Rgba<T> {
+    const CHANNELS: usize = 4;
+    const LINEAR: bool = T::LINEAR;
+
+    fn to_rgba8(&self) -> Rgba {
+        let a = u8::from_unit(self.a.to_unit());
+        Rgba::new(self.r.to_u8(), self.g.to_u8(), self.b.to_u8(), a)
+    }
+
+    fn from_rgba8(rgba: &Rgba) -> Self {
+        let a = T::from_unit(rgba.a.to_unit());
+        Self::new(
+            T::from_u8(rgba.r),
+            T::from_u8(rgba.g),
+            T::from_u8(rgba.b),
+            a,
+        )
+    }
+
+    fn to_linear_rgba(&self) -> [f32; 4] {
+        [
+            self.r.to_linear(),
+            self.g.to_linear(),
+            self.b.to_linear(),
+            self.a.to_unit(),
+        ]
+    }
+
+    fn from_linear_rgba([r, g, b, a]: [f32; 4]) -> Self {
+        let a = T::from_unit(a);
+        Self::new(T::from_linear(r), T::from_linear(g), T::from_linear(b), a)
+    }
+
+    // 32 bpp BGRA with a V4 header, whose alpha mask is what makes most
+    // readers pick up the transparency
+    fn encoder_options() -> BmpEncoderOptions {
+        BmpEncoderOptions::new()
+            .bits_per_pixel(32)
+            .header_version(HeaderVersion::V4)
+            .compression(Compression::Bitfields)
+    }
+}
+
+// Grayscale, colors are reduced to their luminance
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Luma<T = u8>(pub T);
+
+impl<T: Channel> Pixel for Luma<T> {
+    const CHANNELS: usize = 1;
+    const LINEAR: bool = T::LINEAR;
+
+    fn to_rgba8(&self) -> Rgba {
+        let l = self.0.to_u8();
+        Rgba::new(l, l, l, 255)
+    }
+
+    fn from_rgba8(rgba: &Rgba) -> Self {
+        Self::from_linear_rgba(rgba.to_linear_rgba())
+    }
+
+    fn to_linear_rgba(&self) -> [f32; 4] {
+        let l = self.0.to_linear();
+        [l, l, l, 1.0]
+    }
+
+    // Rec. 709 luminance
+    fn from_linear_rgba([r, g, b, _]: [f32; 4]) -> Self {
+        Self(T::from_linear(0.2126 * r + 0.7152 * g + 0.0722 * b))
+    }
+
+    // 8 bpp with a palette of the grays in the image
+    fn encoder_options() -> BmpEncoderOptions {
+        BmpEncoderOptions::new().bits_per_pixel(8)
+    }
+}
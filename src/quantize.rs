@@ -0,0 +1,335 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{Error, ImageView, Pixel, Rgba};
+
+// How to reduce images with more colors than the palette can hold
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quantizer {
+    #[default]
+    MedianCut,
+    Octree,
+    // Fail with Error::TooManyColors instead of losing colors
+    Exact,
+}
+
+// Palette indices for every pixel of the image, in image order
+pub(crate) struct Quantized {
+    pub palette: Vec<Rgba>,
+    pub indices: Vec<u8>,
+}
+
+// Palettes have no alpha, every color is treated as opaque
+pub(crate) fn quantize<P: Pixel>(
+    image: &ImageView<P>,
+    max_colors: usize,
+    quantizer: Quantizer,
+    dither: bool,
+) -> Result<Quantized, Error> {
+    let width = image.width() as usize;
+    let len = width * image.height() as usize;
+    // by index in image order, views don't have contiguous rows
+    let color = |i: usize| -> [u8; 3] {
+        let p = image
+            .get_pixel((i % width) as u32, (i / width) as u32)
+            .to_rgba8();
+        [p.r, p.g, p.b]
+    };
+
+    // Images that already fit get an exact palette
+    if let Some(palette) = exact_palette(len, color, max_colors) {
+        let indices = palette
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (*c, i as u8))
+            .collect::<HashMap<_, _>>();
+        let indices = (0..len).map(|i| indices[&color(i)]).collect();
+        return Ok(to_quantized(palette, indices));
+    }
+
+    let histogram = histogram(len, color);
+    let palette = match quantizer {
+        Quantizer::MedianCut => median_cut(histogram, max_colors),
+        Quantizer::Octree => octree(histogram, max_colors),
+        Quantizer::Exact => return Err(Error::TooManyColors(max_colors)),
+    };
+
+    let indices = if dither {
+        floyd_steinberg(len, width, color, &palette)
+    } else {
+        let mut cache = HashMap::new();
+        (0..len)
+            .map(|i| {
+                let c = color(i);
+                *cache
+                    .entry(c)
+                    .or_insert_with(|| nearest(&palette, [c[0] as f32, c[1] as f32, c[2] as f32]))
+            })
+            .collect()
+    };
+
+    Ok(to_quantized(palette, indices))
+}
+
+fn to_quantized(palette: Vec<[u8; 3]>, indices: Vec<u8>) -> Quantized {
+    Quantized {
+        palette: palette
+            .into_iter()
+            .map(|[r, g, b]| Rgba::new(r, g, b, 255))
+            .collect(),
+        indices,
+    }
+}
+
+fn exact_palette(
+    len: usize,
+    color: impl Fn(usize) -> [u8; 3],
+    max_colors: usize,
+) -> Option<Vec<[u8; 3]>> {
+    let mut palette = vec![];
+    let mut seen = HashMap::new();
+
+    for i in 0..len {
+        let c = color(i);
+        if let Entry::Vacant(entry) = seen.entry(c) {
+            if palette.len() == max_colors {
+                return None;
+            }
+            entry.insert(palette.len());
+            palette.push(c);
+        }
+    }
+
+    Some(palette)
+}
+
+fn histogram(len: usize, color: impl Fn(usize) -> [u8; 3]) -> Vec<([u8; 3], u64)> {
+    let mut counts = HashMap::new();
+    for i in 0..len {
+        *counts.entry(color(i)).or_insert(0) += 1;
+    }
+
+    counts.into_iter().collect()
+}
+
+// Repeatedly splits the box with the widest channel range at its weighted
+// median, each final box becomes one palette entry
+fn median_cut(histogram: Vec<([u8; 3], u64)>, max_colors: usize) -> Vec<[u8; 3]> {
+    let mut boxes = vec![histogram];
+
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, widest_channel(b)))
+            .max_by_key(|(_, (_, range))| *range);
+        let Some((i, (channel, _))) = widest else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|(c, _)| c[channel]);
+
+        let total = colors.iter().map(|(_, n)| n).sum::<u64>();
+        let mut seen = 0;
+        let mut split = 1;
+        for (k, (_, n)) in colors.iter().enumerate() {
+            seen += n;
+            if seen * 2 >= total {
+                split = (k + 1).clamp(1, colors.len() - 1);
+                break;
+            }
+        }
+
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|b| average(b)).collect()
+}
+
+fn widest_channel(colors: &[([u8; 3], u64)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let min = colors.iter().map(|(c, _)| c[channel]).min().unwrap_or(0);
+            let max = colors.iter().map(|(c, _)| c[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn average(colors: &[([u8; 3], u64)]) -> [u8; 3] {
+    let mut sum = [0; 3];
+    let mut total = 0;
+    for (c, n) in colors {
+        for (sum, channel) in sum.iter_mut().zip(c) {
+            *sum += *channel as u64 * n;
+        }
+        total += n;
+    }
+
+    sum.map(|s| ((s + total / 2) / total.max(1)) as u8)
+}
+
+#[derive(Default)]
+struct OctreeNode {
+    // Indices into the node arena, 0 is the root so it doubles as "none"
+    children: [usize; 8],
+    sum: [u64; 3],
+    count: u64,
+    leaf: bool,
+}
+
+// Builds an 8 level octree of the colors, then folds the least used nodes
+// into their parents, deepest first, until few enough leaves remain
+fn octree(histogram: Vec<([u8; 3], u64)>, max_colors: usize) -> Vec<[u8; 3]> {
+    let mut nodes = vec![OctreeNode::default()];
+    // Inner nodes on each level, the candidates for folding
+    let mut levels = vec![vec![]; 8];
+    levels[0].push(0);
+    let mut leaves = 0;
+
+    for (c, n) in histogram {
+        let mut node = 0;
+        for level in 0..8 {
+            nodes[node].count += n;
+            let bit = 7 - level;
+            let child = (((c[0] >> bit) & 1) << 2 | ((c[1] >> bit) & 1) << 1 | ((c[2] >> bit) & 1))
+                as usize;
+
+            if nodes[node].children[child] == 0 {
+                nodes.push(OctreeNode {
+                    leaf: level == 7,
+                    ..Default::default()
+                });
+                nodes[node].children[child] = nodes.len() - 1;
+                if level == 7 {
+                    leaves += 1;
+                } else {
+                    levels[level + 1].push(nodes.len() - 1);
+                }
+            }
+            node = nodes[node].children[child];
+        }
+
+        for (sum, channel) in nodes[node].sum.iter_mut().zip(c) {
+            *sum += channel as u64 * n;
+        }
+        nodes[node].count += n;
+    }
+
+    for level in levels.iter_mut().rev() {
+        // fold the least used nodes first
+        level.sort_unstable_by_key(|&node| std::cmp::Reverse(nodes[node].count));
+
+        while leaves > max_colors {
+            let Some(node) = level.pop() else {
+                break;
+            };
+
+            let mut children = nodes[node]
+                .children
+                .into_iter()
+                .filter(|&child| child != 0)
+                .collect::<Vec<_>>();
+
+            // folding all the children would leave too few colors, merge
+            // the least used pairs of them until there are just enough
+            if leaves + 1 - children.len() < max_colors {
+                while leaves > max_colors {
+                    children.sort_unstable_by_key(|&child| nodes[child].count);
+                    let least = children.remove(0);
+                    let into = children[0];
+                    let (sum, count) = (nodes[least].sum, nodes[least].count);
+                    for (sum, least_sum) in nodes[into].sum.iter_mut().zip(sum) {
+                        *sum += least_sum;
+                    }
+                    nodes[into].count += count;
+                    nodes[least].leaf = false;
+                    leaves -= 1;
+                }
+                break;
+            }
+
+            let mut sum = [0; 3];
+            for &child in &children {
+                for (sum, child_sum) in sum.iter_mut().zip(nodes[child].sum) {
+                    *sum += child_sum;
+                }
+                nodes[child].leaf = false;
+            }
+
+            nodes[node].sum = sum;
+            nodes[node].children = [0; 8];
+            nodes[node].leaf = true;
+            leaves = leaves + 1 - children.len();
+        }
+    }
+
+    nodes
+        .iter()
+        .filter(|node| node.leaf && node.count > 0)
+        .map(|node| node.sum.map(|s| ((s + node.count / 2) / node.count) as u8))
+        .collect()
+}
+
+fn nearest(palette: &[[u8; 3]], color: [f32; 3]) -> u8 {
+    let distance = |c: &[u8; 3]| -> f32 {
+        (0..3)
+            .map(|channel| (c[channel] as f32 - color[channel]).powi(2))
+            .sum()
+    };
+
+    let mut best = (0, f32::MAX);
+    for (i, c) in palette.iter().enumerate() {
+        let d = distance(c);
+        if d < best.1 {
+            best = (i, d);
+        }
+    }
+
+    best.0 as u8
+}
+
+// Spreads each pixel's quantization error over its unvisited neighbours,
+// 7/16 right, 3/16 down-left, 5/16 down and 1/16 down-right
+fn floyd_steinberg(
+    len: usize,
+    width: usize,
+    color: impl Fn(usize) -> [u8; 3],
+    palette: &[[u8; 3]],
+) -> Vec<u8> {
+    let height = len / width;
+    let mut indices = Vec::with_capacity(len);
+    let mut errors = vec![[0.0f32; 3]; width + 2];
+    let mut next_errors = vec![[0.0f32; 3]; width + 2];
+
+    for i in 0..height {
+        for j in 0..width {
+            let c = color(i * width + j);
+            // errors are offset by one so the left neighbour of column 0 exists
+            let e = errors[j + 1];
+            let wanted =
+                [0, 1, 2].map(|channel| (c[channel] as f32 + e[channel]).clamp(0.0, 255.0));
+
+            let index = nearest(palette, wanted);
+            indices.push(index);
+
+            let chosen = palette[index as usize];
+            for channel in 0..3 {
+                let error = wanted[channel] - chosen[channel] as f32;
+                errors[j + 2][channel] += error * 7.0 / 16.0;
+                next_errors[j][channel] += error * 3.0 / 16.0;
+                next_errors[j + 1][channel] += error * 5.0 / 16.0;
+                next_errors[j + 2][channel] += error / 16.0;
+            }
+        }
+
+        std::mem::swap(&mut errors, &mut next_errors);
+        next_errors.fill([0.0; 3]);
+    }
+
+    indices
+}
@@ -0,0 +1,224 @@
+use std::f32::consts::PI;
+
+use crate::{
+    pixel::{linear_to_srgb, srgb_to_linear},
+    Image, Pixel,
+};
+
+// How pixels in between source pixels are interpolated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    // Catmull-Rom
+    Bicubic,
+    // Sharpest, with a three pixel wide window
+    #[default]
+    Lanczos3,
+}
+
+impl Filter {
+    // How far from its center the kernel reaches, in source pixels
+    fn support(self) -> f32 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Bicubic => 2.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn kernel(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest => (x < 0.5) as u8 as f32,
+            Filter::Bilinear => (1.0 - x).max(0.0),
+            Filter::Bicubic if x < 1.0 => 1.5 * x.powi(3) - 2.5 * x.powi(2) + 1.0,
+            Filter::Bicubic if x < 2.0 => -0.5 * x.powi(3) + 2.5 * x.powi(2) - 4.0 * x + 2.0,
+            Filter::Bicubic => 0.0,
+            Filter::Lanczos3 if x < 3.0 => sinc(x) * sinc(x / 3.0),
+            Filter::Lanczos3 => 0.0,
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ResizeOptions {
+    filter: Filter,
+    // Filter linear light instead of the sRGB encoded values. Slower, but
+    // keeps fine detail from darkening and colors from shifting. Float
+    // pixels are linear light already and always filtered that way.
+    linear_light: bool,
+}
+
+impl ResizeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn resize(&self, width: u32, height: u32, filter: Filter) -> Self {
+        self.resize_with(width, height, &ResizeOptions::new().filter(filter))
+    }
+
+    // Resamples the rows to the new width, then the columns to the new
+    // height. Empty images stay empty.
+    pub fn resize_with(&self, width: u32, height: u32, options: &ResizeOptions) -> Self {
+        let (src_width, src_height) = (self.width as usize, self.height() as usize);
+        let (width, height) = (width as usize, height as usize);
+        if src_width == 0 || src_height == 0 || width == 0 || height == 0 {
+            return Self {
+                pixels: vec![],
+                width: width as u32,
+                resolution: self.resolution,
+            };
+        }
+
+        // premultiplied by alpha, so transparent pixels don't bleed color.
+        // Float pixels are always filtered as they are, sRGB would clip them.
+        let to_srgb = !options.linear_light && !P::LINEAR;
+        let samples = self
+            .pixels
+            .iter()
+            .map(|p| {
+                let [r, g, b, a] = p.to_linear_rgba();
+                let [r, g, b] = [r, g, b].map(|c| if to_srgb { linear_to_srgb(c) } else { c });
+                [r * a, g * a, b * a, a]
+            })
+            .collect::<Vec<_>>();
+
+        // horizontal pass, row by row
+        let columns = weights(src_width, width, options.filter);
+        let mut wide = vec![[0.0; 4]; width * src_height];
+        for (src, out) in samples
+            .chunks_exact(src_width)
+            .zip(wide.chunks_exact_mut(width))
+        {
+            for (out, weights) in out.iter_mut().zip(&columns) {
+                for (sample, weight) in src[weights.start..].iter().zip(&weights.values) {
+                    add(out, sample, *weight);
+                }
+            }
+        }
+
+        // vertical pass, adding whole rows at a time to stay in cache
+        let rows = weights(src_height, height, options.filter);
+        let mut resized = vec![[0.0; 4]; width * height];
+        for (out, weights) in resized.chunks_exact_mut(width).zip(&rows) {
+            for (i, weight) in weights.values.iter().enumerate() {
+                let src = &wide[(weights.start + i) * width..][..width];
+                for (out, sample) in out.iter_mut().zip(src) {
+                    add(out, sample, *weight);
+                }
+            }
+        }
+
+        let pixels = resized
+            .into_iter()
+            .map(|[r, g, b, a]| {
+                // ringing filters can overshoot
+                let a = a.clamp(0.0, 1.0);
+                let [r, g, b] = [r, g, b].map(|c| {
+                    let c = if a > 0.0 { (c / a).max(0.0) } else { 0.0 };
+                    if to_srgb {
+                        srgb_to_linear(c.min(1.0))
+                    } else {
+                        c
+                    }
+                });
+                P::from_linear_rgba([r, g, b, a])
+            })
+            .collect();
+
+        Self {
+            pixels,
+            width: width as u32,
+            resolution: self.resolution,
+        }
+    }
+
+    // Shrinks the image to fit in max_width x max_height, keeping its
+    // aspect ratio. Images that already fit are copied as they are.
+    pub fn thumbnail(&self, max_width: u32, max_height: u32) -> Self {
+        self.thumbnail_with(max_width, max_height, &ResizeOptions::new())
+    }
+
+    pub fn thumbnail_with(&self, max_width: u32, max_height: u32, options: &ResizeOptions) -> Self {
+        let (width, height) = (self.width, self.height());
+        if width <= max_width && height <= max_height {
+            return self.clone();
+        }
+
+        let scale = f64::min(
+            max_width as f64 / width as f64,
+            max_height as f64 / height as f64,
+        );
+        let fit = |len: u32| ((len as f64 * scale).round() as u32).max(1);
+
+        self.resize_with(fit(width), fit(height), options)
+    }
+}
+
+// The source pixels one output pixel is made of, and how much of each
+struct Weights {
+    start: usize,
+    values: Vec<f32>,
+}
+
+fn weights(src_len: usize, len: usize, filter: Filter) -> Vec<Weights> {
+    let scale = src_len as f32 / len as f32;
+    // when shrinking the kernel is stretched over every source pixel the
+    // output pixel covers
+    let stretch = scale.max(1.0);
+    let support = filter.support() * stretch;
+
+    (0..len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            if filter == Filter::Nearest {
+                let start = (center as usize).min(src_len - 1);
+                return Weights {
+                    start,
+                    values: vec![1.0],
+                };
+            }
+
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(src_len);
+            let mut values = (start..end)
+                .map(|j| filter.kernel((j as f32 + 0.5 - center) / stretch))
+                .collect::<Vec<_>>();
+            let total = values.iter().sum::<f32>();
+            if total != 0.0 {
+                values.iter_mut().for_each(|v| *v /= total);
+            }
+
+            Weights { start, values }
+        })
+        .collect()
+}
+
+fn add(out: &mut [f32; 4], sample: &[f32; 4], weight: f32) {
+    for (out, sample) in out.iter_mut().zip(sample) {
+        *out += sample * weight;
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{Error, Image, Pixel, Resolution};
+
+// Side of the square tiles transposing copies go through, so both the rows
+// read and the rows written stay in cache
+const TILE: usize = 64;
+
+impl<P: Pixel> Image<P> {
+    pub fn flip_horizontal(&self) -> Self {
+        let width = self.width as usize;
+        let pixels = self
+            .pixels
+            .chunks_exact(width.max(1))
+            .flat_map(|row| row.iter().rev())
+            .cloned()
+            .collect();
+
+        Self {
+            pixels,
+            width: self.width,
+            resolution: self.resolution,
+        }
+    }
+
+    pub fn flip_horizontal_in_place(&mut self) {
+        let width = self.width as usize;
+        for row in self.pixels.chunks_exact_mut(width.max(1)) {
+            row.reverse();
+        }
+    }
+
+    pub fn flip_vertical(&self) -> Self {
+        let width = self.width as usize;
+        let pixels = self
+            .pixels
+            .chunks_exact(width.max(1))
+            .rev()
+            .flatten()
+            .cloned()
+            .collect();
+
+        Self {
+            pixels,
+            width: self.width,
+            resolution: self.resolution,
+        }
+    }
+
+    // Swaps whole rows, top with bottom
+    pub fn flip_vertical_in_place(&mut self) {
+        let width = self.width as usize;
+        let height = self.height() as usize;
+        for i in 0..height / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((height - i - 1) * width);
+            top[i * width..(i + 1) * width].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+
+    pub fn rotate180(&self) -> Self {
+        Self {
+            pixels: self.pixels.iter().rev().cloned().collect(),
+            width: self.width,
+            resolution: self.resolution,
+        }
+    }
+
+    pub fn rotate180_in_place(&mut self) {
+        self.pixels.reverse();
+    }
+
+    // Clockwise
+    pub fn rotate90(&self) -> Self {
+        let height = self.height() as usize;
+        self.transposed(|x, y| x * height + (height - y - 1))
+    }
+
+    // Square images are rotated by swapping pixels, others are copied and
+    // briefly take twice the memory, like transpose_in_place
+    pub fn rotate90_in_place(&mut self) {
+        if self.width != self.height() {
+            *self = self.rotate90();
+            return;
+        }
+
+        self.transpose_in_place();
+        self.flip_horizontal_in_place();
+    }
+
+    // Counterclockwise by 90, clockwise by 270
+    pub fn rotate270(&self) -> Self {
+        let (width, height) = (self.width as usize, self.height() as usize);
+        self.transposed(|x, y| (width - x - 1) * height + y)
+    }
+
+    // Copies non-square images, see rotate90_in_place
+    pub fn rotate270_in_place(&mut self) {
+        if self.width != self.height() {
+            *self = self.rotate270();
+            return;
+        }
+
+        self.transpose_in_place();
+        self.flip_vertical_in_place();
+    }
+
+    // Mirrors along the diagonal from the top left corner
+    pub fn transpose(&self) -> Self {
+        let height = self.height() as usize;
+        self.transposed(|x, y| x * height + y)
+    }
+
+    // Square images are transposed by swapping pixels, others need a copy
+    // since rows and columns change length, briefly taking twice the memory
+    pub fn transpose_in_place(&mut self) {
+        let width = self.width as usize;
+        if width != self.height() as usize {
+            *self = self.transpose();
+            return;
+        }
+        self.resolution = Resolution::new(self.resolution.y, self.resolution.x);
+
+        for ty in (0..width).step_by(TILE) {
+            for tx in (ty..width).step_by(TILE) {
+                for y in ty..(ty + TILE).min(width) {
+                    // tiles on the diagonal only swap below it
+                    let start = if tx == ty { y + 1 } else { tx };
+                    for x in start..(tx + TILE).min(width) {
+                        self.pixels.swap(y * width + x, x * width + y);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Self, Error> {
+        Ok(self.view(x, y, width, height)?.to_image())
+    }
+
+    // Moves the rows of the rectangle to the front and drops the rest
+    pub fn crop_in_place(&mut self, x: u32, y: u32, width: u32, height: u32) -> Result<(), Error> {
+        self.view(x, y, width, height)?;
+
+        let stride = self.width as usize;
+        let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+        for row in 0..height {
+            let src = (y + row) * stride + x;
+            let dst = row * width;
+            // dst never passes src, so what's swapped back is never read
+            for i in 0..width {
+                self.pixels.swap(dst + i, src + i);
+            }
+        }
+        self.pixels.truncate(width * height);
+        self.width = width as u32;
+
+        Ok(())
+    }
+
+    // Copies every pixel to index(x, y) of a width and height swapped image,
+    // a tile at a time
+    fn transposed(&self, index: impl Fn(usize, usize) -> usize) -> Self {
+        let (width, height) = (self.width as usize, self.height() as usize);
+        // filler, every pixel is overwritten
+        let mut pixels = match self.pixels.first() {
+            Some(first) => vec![first.clone(); self.pixels.len()],
+            None => vec![],
+        };
+        for ty in (0..height).step_by(TILE) {
+            for tx in (0..width).step_by(TILE) {
+                for y in ty..(ty + TILE).min(height) {
+                    for x in tx..(tx + TILE).min(width) {
+                        pixels[index(x, y)] = self.pixels[y * width + x].clone();
+                    }
+                }
+            }
+        }
+
+        Self {
+            pixels,
+            width: height as u32,
+            resolution: Resolution::new(self.resolution.y, self.resolution.x),
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::{fs::File, io::Write, path::Path};
+
+use crate::{encode, BmpEncoderOptions, Error, Image, Pixel, Resolution};
+
+// A borrowed rectangle of an image. Rows keep the stride of the image they
+// come from, so making a view copies nothing.
+#[derive(Debug)]
+pub struct ImageView<'a, P> {
+    // From the first pixel of the top row to the last of the bottom one,
+    // empty for empty views
+    pixels: &'a [P],
+    width: u32,
+    height: u32,
+    // Pixels from the start of one row to the next
+    stride: usize,
+    pub resolution: Resolution,
+}
+
+// Like ImageView, but the pixels can be changed in place
+#[derive(Debug)]
+pub struct ImageViewMut<'a, P> {
+    pixels: &'a mut [P],
+    width: u32,
+    height: u32,
+    stride: usize,
+    pub resolution: Resolution,
+}
+
+// Where a rectangle of a view is in the view's pixels, or an error when it
+// doesn't fit
+fn region(
+    view_width: u32,
+    view_height: u32,
+    stride: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Result<std::ops::Range<usize>, Error> {
+    let in_bounds = x
+        .checked_add(width)
+        .is_some_and(|right| right <= view_width)
+        && y.checked_add(height)
+            .is_some_and(|bottom| bottom <= view_height);
+    if !in_bounds {
+        return Err(Error::RegionOutOfBounds {
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+    if width == 0 || height == 0 {
+        return Ok(0..0);
+    }
+
+    let start = y as usize * stride + x as usize;
+    let end = (y + height - 1) as usize * stride + (x + width) as usize;
+
+    Ok(start..end)
+}
+
+impl<P: Pixel> Image<P> {
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            pixels: self.pixels(),
+            width: self.width(),
+            height: self.height(),
+            stride: self.width() as usize,
+            resolution: self.resolution,
+        }
+    }
+
+    pub fn as_view_mut(&mut self) -> ImageViewMut<'_, P> {
+        let (width, height) = (self.width(), self.height());
+        ImageViewMut {
+            resolution: self.resolution,
+            pixels: self.pixels_mut(),
+            width,
+            height,
+            stride: width as usize,
+        }
+    }
+
+    // The rectangle with its top left corner at x, y
+    pub fn view(&self, x: u32, y: u32, width: u32, height: u32) -> Result<ImageView<'_, P>, Error> {
+        self.as_view().into_view(x, y, width, height)
+    }
+
+    pub fn view_mut(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageViewMut<'_, P>, Error> {
+        self.as_view_mut().into_view_mut(x, y, width, height)
+    }
+}
+
+impl<'a, P: Pixel> ImageView<'a, P> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Panics outside the view
+    pub fn get_pixel(&self, x: u32, y: u32) -> &'a P {
+        &self.row(y)[x as usize]
+    }
+
+    // Panics outside the view
+    pub fn row(&self, y: u32) -> &'a [P] {
+        assert!(y < self.height, "row {y} is outside the view");
+        let start = y as usize * self.stride;
+        &self.pixels[start..start + self.width as usize]
+    }
+
+    // From the top
+    pub fn rows(&self) -> impl Iterator<Item = &'a [P]> + 'a {
+        let width = self.width as usize;
+        self.pixels
+            .chunks(self.stride.max(1))
+            .map(move |row| &row[..width])
+    }
+
+    // A rectangle of this view, x and y are relative to its top left corner
+    pub fn view(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Self, Error> {
+        (*self).into_view(x, y, width, height)
+    }
+
+    fn into_view(self, x: u32, y: u32, width: u32, height: u32) -> Result<Self, Error> {
+        let range = region(self.width, self.height, self.stride, x, y, width, height)?;
+
+        Ok(Self {
+            pixels: &self.pixels[range],
+            width,
+            height,
+            ..self
+        })
+    }
+
+    // Copies the pixels into an image of their own
+    pub fn to_image(&self) -> Image<P> {
+        let pixels = self.rows().flatten().cloned().collect();
+        let mut image = Image::new(pixels, self.width);
+        image.resolution = self.resolution;
+        image
+    }
+
+    // Encodes only this rectangle, with P::encoder_options() like
+    // Image::write_bmp
+    pub fn write_bmp<W: Write>(&self, writer: W) -> Result<(), Error> {
+        self.write_bmp_with(writer, &P::encoder_options())
+    }
+
+    pub fn write_bmp_with<W: Write>(
+        &self,
+        mut writer: W,
+        options: &BmpEncoderOptions,
+    ) -> Result<(), Error> {
+        let buff = encode(self, options)?;
+        writer.write_all(&buff)?;
+
+        Ok(())
+    }
+
+    pub fn save_bmp<T: AsRef<Path>>(&self, file_path: T) -> Result<(), Error> {
+        self.save_bmp_with(file_path, &P::encoder_options())
+    }
+
+    pub fn save_bmp_with<T: AsRef<Path>>(
+        &self,
+        file_path: T,
+        options: &BmpEncoderOptions,
+    ) -> Result<(), Error> {
+        self.write_bmp_with(File::create(file_path)?, options)
+    }
+}
+
+impl<'a, P: Pixel> ImageViewMut<'a, P> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            pixels: &*self.pixels,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            resolution: self.resolution,
+        }
+    }
+
+    // Panics outside the view
+    pub fn get_pixel(&self, x: u32, y: u32) -> &P {
+        &self.row(y)[x as usize]
+    }
+
+    // Panics outside the view
+    pub fn put_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        self.row_mut(y)[x as usize] = pixel;
+    }
+
+    // Panics outside the view
+    pub fn row(&self, y: u32) -> &[P] {
+        self.as_view().row(y)
+    }
+
+    // Panics outside the view
+    pub fn row_mut(&mut self, y: u32) -> &mut [P] {
+        assert!(y < self.height, "row {y} is outside the view");
+        let start = y as usize * self.stride;
+        &mut self.pixels[start..start + self.width as usize]
+    }
+
+    // From the top
+    pub fn rows(&self) -> impl Iterator<Item = &[P]> + '_ {
+        self.as_view().rows()
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [P]> + '_ {
+        let width = self.width as usize;
+        self.pixels
+            .chunks_mut(self.stride.max(1))
+            .map(move |row| &mut row[..width])
+    }
+
+    pub fn fill(&mut self, color: P) {
+        for row in self.rows_mut() {
+            row.fill(color.clone());
+        }
+    }
+
+    // A rectangle of this view, x and y are relative to its top left corner
+    pub fn view(&self, x: u32, y: u32, width: u32, height: u32) -> Result<ImageView<'_, P>, Error> {
+        self.as_view().into_view(x, y, width, height)
+    }
+
+    pub fn view_mut(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageViewMut<'_, P>, Error> {
+        ImageViewMut {
+            pixels: &mut *self.pixels,
+            ..*self
+        }
+        .into_view_mut(x, y, width, height)
+    }
+
+    fn into_view_mut(self, x: u32, y: u32, width: u32, height: u32) -> Result<Self, Error> {
+        let range = region(self.width, self.height, self.stride, x, y, width, height)?;
+
+        Ok(Self {
+            pixels: &mut self.pixels[range],
+            width,
+            height,
+            ..self
+        })
+    }
+
+    pub fn to_image(&self) -> Image<P> {
+        self.as_view().to_image()
+    }
+}
+
+// Deriving would require P: Clone
+impl<P> Clone for ImageView<'_, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P> Copy for ImageView<'_, P> {}